@@ -71,4 +71,12 @@ download_file \
     "https://raw.githubusercontent.com/PaddlePaddle/PaddleOCR/main/ppocr/utils/en_dict.txt" \
     "$MODELS_DIR/en_dict.txt"
 
+# Verify the files whose digests are known
+if command -v sha256sum &> /dev/null; then
+    if ! (cd "$MODELS_DIR" && grep -v '^#' checksums.sha256 | sha256sum -c --ignore-missing --quiet); then
+        echo "Checksum mismatch. Delete the files listed above and run this script again."
+        exit 1
+    fi
+fi
+
 echo "All models downloaded successfully to '$MODELS_DIR/'."
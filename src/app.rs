@@ -1,31 +1,84 @@
 use eframe::{App, CreationContext, Frame};
 use egui::{CentralPanel, Context, ProgressBar, ScrollArea, SidePanel, TopBottomPanel, Vec2};
-use ort::session::Session;
 use rusqlite::Connection;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex, mpsc, atomic::{AtomicBool, Ordering}};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, mpsc, atomic::{AtomicBool, Ordering}};
 use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::clip;
+use crate::colors::{self, PaletteColor};
 use crate::db;
+use crate::detection::Detection;
+use crate::duplicates;
+use crate::faces;
+use crate::file_ops::{self, Destination, KeepRule, ResolvePlan};
+use crate::health;
+use crate::manifest;
 use crate::ml;
+use crate::objects;
 use crate::processing;
+use crate::query;
+use crate::scanner;
+use crate::tagger::Tagger;
+use crate::thumbnails;
 
 pub enum AppMessage {
     Log(String),
     Progress(usize, usize),
     Finished,
+    /// Result of a library maintenance scan.
+    StaleEntries(Vec<processing::StaleEntry>),
+    /// Result of the startup model check.
+    ModelHealth(Vec<health::ModelStatus>),
+    /// Result of a duplicate scan.
+    Duplicates(Vec<duplicates::DuplicateGroup>),
+}
+
+/// Image shown in the Details window.
+struct Details {
+    path: std::path::PathBuf,
+    offline: bool,
+    tags: Vec<crate::tags::ScoredTag>,
+    detections: Vec<Detection>,
+    palette: Vec<PaletteColor>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortOrder {
+    Relevance,
+    Confidence,
+    Name,
 }
 
 pub struct ImageTagger {
-    folder: String,
+    roots: Vec<db::LibraryRoot>,
+    offline_roots: HashSet<i64>,
+    search_root: Option<i64>,
+    tagging_config: ml::TaggingConfig,
     query: String,
+    query_error: Option<String>,
+    min_confidence: f32,
+    sort_order: SortOrder,
     rename_old: String,
     rename_new: String,
-    results: Vec<PathBuf>,
+    results: Vec<db::SearchHit>,
     conn: Connection,
-    session: Option<Arc<Mutex<Session>>>,
-    labels: Arc<Vec<ml::Label>>,
-    ocr: Arc<crate::ocr::OcrModel>,
+    taggers: Vec<Arc<dyn Tagger>>,
+    /// Names of taggers switched off in the settings.
+    disabled_taggers: HashSet<String>,
+    /// Text encoder for `about:` searches; also one of `taggers`.
+    clip: Option<Arc<clip::ClipModel>>,
+    /// Search bare words by meaning instead of by tag and text.
+    semantic_search: bool,
+    /// Only show images with a palette color near `filter_color`.
+    color_filter: bool,
+    filter_color: [u8; 3],
+    /// CIE76 ΔE.
+    color_tolerance: f32,
+    /// Image the results are "More like this" of, instead of a query.
+    similar_to: Option<std::path::PathBuf>,
+    min_similarity: f32,
     
     // Threading
     receiver: mpsc::Receiver<AppMessage>,
@@ -34,6 +87,37 @@ pub struct ImageTagger {
     progress: (usize, usize),
     logs: Vec<String>,
     cancellation_token: Arc<AtomicBool>,
+
+    // Library maintenance
+    maintenance_running: bool,
+    maintenance_token: Arc<AtomicBool>,
+    stale_entries: Vec<(processing::StaleEntry, bool)>,
+    show_maintenance: bool,
+
+    // Model health
+    model_check_running: bool,
+    model_health: Vec<health::ModelStatus>,
+
+    // Duplicates
+    duplicates_running: bool,
+    duplicate_groups: Vec<duplicates::DuplicateGroup>,
+    duplicate_distance: u32,
+    show_duplicates: bool,
+    keep_rule: KeepRule,
+    destination: Destination,
+    /// Resolution waiting for confirmation.
+    resolve_plan: Option<ResolvePlan>,
+    /// Latest resolution that can still be undone.
+    undoable: Vec<db::FileOp>,
+
+    // People
+    people: Vec<db::Person>,
+    /// Names being typed, by person id.
+    person_names: HashMap<i64, String>,
+    show_people: bool,
+
+    details: Option<Details>,
+    show_boxes: bool,
 }
 
 impl ImageTagger {
@@ -45,43 +129,713 @@ impl ImageTagger {
         // Initialize ORT
         let _ = ort::init().with_name("local_lens").commit();
 
-        let session = ml::find_model_file("resnet50-v2-7.onnx")
-            .and_then(|path| {
-                Session::builder()
-                    .ok()
-                    .and_then(|b| b.commit_from_file(path).ok())
-            })
-            .map(|s| Arc::new(Mutex::new(s)));
+        let mut taggers: Vec<Arc<dyn Tagger>> = Vec::new();
+        let mut startup_logs = Vec::new();
+        for manifest in manifest::discover() {
+            let loaded = manifest.and_then(|m| {
+                let name = m.name.clone();
+                ml::ClassifierTagger::load(m).map_err(|e| anyhow::anyhow!("{}: {}", name, e))
+            });
+            match loaded {
+                Ok(classifier) => taggers.push(Arc::new(classifier)),
+                Err(e) => startup_logs.push(format!("Classifier unavailable: {}", e)),
+            }
+        }
+        match crate::ocr::OcrModel::load() {
+            Ok(model) => taggers.push(Arc::new(model)),
+            Err(e) => startup_logs.push(format!("Text search unavailable: {}", e)),
+        }
+        taggers.push(Arc::new(colors::ColorAnalyzer));
+        match objects::ObjectDetector::load() {
+            Ok(model) => taggers.push(Arc::new(model)),
+            Err(e) => startup_logs.push(format!("Object detection unavailable: {}", e)),
+        }
+        match faces::FaceModel::load() {
+            Ok(model) => taggers.push(Arc::new(model)),
+            Err(e) => startup_logs.push(format!("Face recognition unavailable: {}", e)),
+        }
+        let clip = match clip::ClipModel::load() {
+            Ok(model) => {
+                let model = Arc::new(model);
+                taggers.push(model.clone());
+                Some(model)
+            }
+            Err(e) => {
+                startup_logs.push(format!("Semantic search unavailable: {}", e));
+                None
+            }
+        };
 
-        let labels = Arc::new(ml::load_labels().unwrap_or_default());
         let (sender, receiver) = mpsc::channel();
-        let ocr = Arc::new(crate::ocr::OcrModel::new());
 
-        Self {
-            folder: String::new(),
+        let mut app = Self {
+            roots: Vec::new(),
+            offline_roots: HashSet::new(),
+            search_root: None,
+            tagging_config: ml::TaggingConfig::default(),
             query: String::new(),
+            query_error: None,
+            min_confidence: 0.0,
+            sort_order: SortOrder::Relevance,
             rename_old: String::new(),
             rename_new: String::new(),
             results: Vec::new(),
             conn: db::setup_db().unwrap(),
-            session,
-            labels,
-            ocr,
+            taggers,
+            disabled_taggers: HashSet::new(),
+            clip,
+            semantic_search: false,
+            color_filter: false,
+            filter_color: [40, 90, 200],
+            color_tolerance: colors::DEFAULT_TOLERANCE,
+            similar_to: None,
+            min_similarity: 0.75,
             receiver,
             sender,
             is_processing: false,
             progress: (0, 0),
-            logs: Vec::new(),
+            logs: startup_logs,
             cancellation_token: Arc::new(AtomicBool::new(false)),
+            maintenance_running: false,
+            maintenance_token: Arc::new(AtomicBool::new(false)),
+            stale_entries: Vec::new(),
+            show_maintenance: false,
+            model_check_running: false,
+            model_health: Vec::new(),
+            duplicates_running: false,
+            duplicate_groups: Vec::new(),
+            duplicate_distance: duplicates::DEFAULT_MAX_DISTANCE,
+            show_duplicates: false,
+            keep_rule: KeepRule::HighestResolution,
+            destination: Destination::Trash,
+            resolve_plan: None,
+            undoable: Vec::new(),
+            people: Vec::new(),
+            person_names: HashMap::new(),
+            show_people: false,
+            details: None,
+            show_boxes: true,
+        };
+        app.logs.push(format!("Catalog: {}", db::db_path().display()));
+        app.reload_roots();
+        app.reload_undoable();
+        // Check the library quietly in the background on every start
+        app.start_maintenance_scan();
+        app.start_model_check();
+        app
+    }
+
+    fn start_model_check(&mut self) {
+        if self.model_check_running {
+            return;
+        }
+        self.model_check_running = true;
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            health::run_model_check(sender);
+        });
+    }
+
+    fn models_list(&self, ui: &mut egui::Ui) {
+        if self.model_check_running {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Checking models...");
+            });
+            return;
+        }
+        for status in &self.model_health {
+            ui.horizontal(|ui| {
+                if status.is_ok() {
+                    ui.colored_label(egui::Color32::LIGHT_GREEN, "OK");
+                } else {
+                    ui.colored_label(egui::Color32::LIGHT_RED, "Problem");
+                }
+                if status.version.is_empty() {
+                    ui.label(&status.name);
+                } else {
+                    ui.label(format!("{} (v{})", status.name, status.version));
+                }
+            });
+            if let Some(path) = &status.path {
+                ui.weak(path.display().to_string());
+            }
+            for problem in &status.problems {
+                ui.colored_label(egui::Color32::LIGHT_RED, problem);
+            }
         }
     }
+
+    fn reload_roots(&mut self) {
+        match db::get_roots(&self.conn) {
+            Ok(roots) => self.roots = roots,
+            Err(e) => self.logs.push(format!("Failed to load libraries: {}", e)),
+        }
+        self.offline_roots = self.roots.iter()
+            .filter(|r| !r.path.is_dir() || scanner::is_offline(&r.path))
+            .map(|r| r.id)
+            .collect();
+        if self.search_root.is_some_and(|id| !self.roots.iter().any(|r| r.id == id)) {
+            self.search_root = None;
+        }
+    }
+
+    fn open_catalog(&mut self, path: std::path::PathBuf) {
+        let previous = db::db_path();
+        db::set_db_path(path.clone());
+        match db::setup_db() {
+            Ok(conn) => {
+                self.conn = conn;
+                if let Err(e) = db::save_catalog(&path) {
+                    self.logs.push(format!("Could not remember catalog choice: {}", e));
+                }
+                self.logs.push(format!("Catalog: {}", path.display()));
+                self.results.clear();
+                self.stale_entries.clear();
+                self.duplicate_groups.clear();
+                self.people.clear();
+                self.person_names.clear();
+                self.search_root = None;
+                self.reload_roots();
+                self.reload_undoable();
+                self.start_maintenance_scan();
+            }
+            Err(e) => {
+                db::set_db_path(previous);
+                self.logs.push(format!("Failed to open catalog {}: {}", path.display(), e));
+            }
+        }
+    }
+
+    fn start_maintenance_scan(&mut self) {
+        if self.maintenance_running {
+            return;
+        }
+        self.maintenance_running = true;
+        self.maintenance_token.store(false, Ordering::Relaxed);
+        let token = self.maintenance_token.clone();
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            processing::run_maintenance_scan(sender, token);
+        });
+    }
+
+    fn start_duplicate_scan(&mut self) {
+        if self.duplicates_running {
+            return;
+        }
+        self.duplicates_running = true;
+        let (root, distance) = (self.search_root, self.duplicate_distance);
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            duplicates::run_duplicate_scan(root, distance, sender);
+        });
+    }
+
+    fn selected_stale_paths(&self) -> Vec<std::path::PathBuf> {
+        self.stale_entries.iter()
+            .filter(|(_, selected)| *selected)
+            .map(|(entry, _)| entry.path.clone())
+            .collect()
+    }
+
+    fn roots_list(&mut self, ui: &mut egui::Ui) {
+        if self.roots.is_empty() {
+            ui.weak("No folders yet.");
+            return;
+        }
+        let busy = self.is_processing;
+        let mut rescan = None;
+        let mut remove = None;
+        let mut edited = Vec::new();
+        for root in &mut self.roots {
+            let offline = self.offline_roots.contains(&root.id);
+            ui.label(root.path.display().to_string());
+            ui.horizontal(|ui| {
+                if offline {
+                    ui.colored_label(egui::Color32::YELLOW, "offline");
+                } else {
+                    ui.weak(root.last_scan.map(format_age).unwrap_or_else(|| "never scanned".to_string()));
+                }
+                if ui.add_enabled(!busy && !offline, egui::Button::new("Rescan")).clicked() {
+                    rescan = Some(root.clone());
+                }
+                if ui.add_enabled(!busy, egui::Button::new("Remove"))
+                    .on_hover_text("Forget this folder and its index entries. Files are not touched.")
+                    .clicked()
+                {
+                    remove = Some(root.id);
+                }
+            });
+            egui::CollapsingHeader::new("Rules").id_source(("root_rules", root.id)).show(ui, |ui| {
+                let mut changed = false;
+                ui.label("Include (e.g. *.jpg, 2024/*):");
+                changed |= ui.text_edit_singleline(&mut root.include).lost_focus();
+                ui.label("Exclude (e.g. */.thumbnails/*):");
+                changed |= ui.text_edit_singleline(&mut root.exclude).lost_focus();
+                changed |= ui.checkbox(&mut root.recursive, "Include subfolders").changed();
+                if changed {
+                    edited.push(root.clone());
+                }
+            });
+        }
+
+        for root in edited {
+            if let Err(e) = db::update_root(&self.conn, &root) {
+                self.logs.push(format!("Failed to save folder rules: {}", e));
+            }
+        }
+        if let Some(root) = rescan {
+            self.start_tagging(vec![root]);
+        }
+        if let Some(id) = remove {
+            match db::remove_root(&self.conn, id) {
+                Ok(()) => {
+                    self.results.clear();
+                    self.reload_roots();
+                }
+                Err(e) => self.logs.push(format!("Failed to remove folder: {}", e)),
+            }
+        }
+    }
+
+    fn maintenance_window(&mut self, ctx: &Context) {
+        let mut open = self.show_maintenance;
+        egui::Window::new("Library maintenance").open(&mut open).default_width(500.0).show(ctx, |ui| {
+            if self.stale_entries.is_empty() {
+                ui.label("Every indexed file is present.");
+                return;
+            }
+            ui.label(format!("{} indexed files could not be found.", self.stale_entries.len()));
+            ui.horizontal(|ui| {
+                if ui.button("Select deleted").clicked() {
+                    self.stale_entries.iter_mut().for_each(|(e, s)| *s = !e.offline);
+                }
+                if ui.button("Select none").clicked() {
+                    self.stale_entries.iter_mut().for_each(|(_, s)| *s = false);
+                }
+            });
+            ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                for offline in [false, true] {
+                    let count = self.stale_entries.iter().filter(|(e, _)| e.offline == offline).count();
+                    if count == 0 {
+                        continue;
+                    }
+                    ui.strong(if offline {
+                        format!("On drives that are not mounted ({})", count)
+                    } else {
+                        format!("Deleted ({})", count)
+                    });
+                    for (entry, selected) in self.stale_entries.iter_mut().filter(|(e, _)| e.offline == offline) {
+                        ui.checkbox(selected, entry.path.display().to_string());
+                    }
+                }
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Remove selected").clicked() {
+                    let paths = self.selected_stale_paths();
+                    match db::remove_images(&self.conn, &paths) {
+                        Ok(n) => self.logs.push(format!("Removed {} stale entries.", n)),
+                        Err(e) => self.logs.push(format!("DB Update Error: {}", e)),
+                    }
+                    self.stale_entries.retain(|(_, selected)| !selected);
+                    self.results.retain(|hit| !paths.contains(&hit.path));
+                }
+                if ui.button("Mark selected offline").clicked() {
+                    let paths = self.selected_stale_paths();
+                    match db::set_offline(&self.conn, &paths, true) {
+                        Ok(()) => self.logs.push(format!("Marked {} entries offline.", paths.len())),
+                        Err(e) => self.logs.push(format!("DB Update Error: {}", e)),
+                    }
+                    self.stale_entries.retain(|(_, selected)| !selected);
+                    for hit in self.results.iter_mut().filter(|hit| paths.contains(&hit.path)) {
+                        hit.offline = true;
+                    }
+                }
+            });
+        });
+        self.show_maintenance = open;
+    }
     
+    fn duplicates_window(&mut self, ctx: &Context) {
+        let mut open = self.show_duplicates;
+        let mut rescan = false;
+        let mut preview = None;
+        let mut undo = false;
+        egui::Window::new("Duplicates").open(&mut open).default_width(600.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let slider = ui.add(egui::Slider::new(&mut self.duplicate_distance, 0..=16).text("Max difference (bits)"))
+                    .on_hover_text("0 finds only identical-looking images; higher also groups edits and burst shots");
+                rescan = slider.drag_stopped() || (slider.changed() && !slider.dragged());
+                if self.duplicates_running {
+                    ui.spinner();
+                }
+            });
+            if let Some(op) = self.undoable.first() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Last resolution moved {} files to {}.", self.undoable.len(), op.action));
+                    if ui.button("Undo").clicked() {
+                        undo = true;
+                    }
+                });
+            }
+            if self.duplicate_groups.is_empty() {
+                ui.label(if self.duplicates_running { "Looking for duplicates..." } else { "No duplicates found." });
+                return;
+            }
+            let copies: usize = self.duplicate_groups.iter().map(|g| g.images.len() - 1).sum();
+            ui.label(format!("{} groups, {} extra copies.", self.duplicate_groups.len(), copies));
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("keep_rule")
+                    .selected_text(self.keep_rule.label())
+                    .show_ui(ui, |ui| {
+                        for rule in KeepRule::ALL {
+                            ui.selectable_value(&mut self.keep_rule, rule, rule.label());
+                        }
+                    });
+                ui.label("move the rest to");
+                egui::ComboBox::from_id_source("destination")
+                    .selected_text(self.destination.label())
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.destination, Destination::Trash, "Trash");
+                        if let Some(dir) = file_ops::default_quarantine() {
+                            let label = format!("Quarantine ({})", dir.display());
+                            ui.selectable_value(&mut self.destination, Destination::Quarantine(dir), label);
+                        }
+                        if ui.button("Choose folder...").clicked() {
+                            if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                                self.destination = Destination::Quarantine(dir);
+                            }
+                            ui.close_menu();
+                        }
+                    });
+                if ui.button("Resolve all...").clicked() {
+                    let groups: Vec<_> = self.duplicate_groups.iter().collect();
+                    preview = Some(file_ops::plan(&groups, self.keep_rule, self.destination.clone()));
+                }
+            });
+            ScrollArea::vertical().max_height(500.0).show(ui, |ui| {
+                for group in &self.duplicate_groups {
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.strong(if group.exact {
+                            format!("{} identical files", group.images.len())
+                        } else {
+                            format!("{} similar images", group.images.len())
+                        });
+                        if ui.small_button("Resolve...").clicked() {
+                            preview = Some(file_ops::plan(&[group], self.keep_rule, self.destination.clone()));
+                        }
+                    });
+                    for image in &group.images {
+                        ui.horizontal(|ui| {
+                            let source = image.hash.as_deref().and_then(thumbnails::cached);
+                            let uri = format!("file://{}", source.as_deref().unwrap_or(&image.path).display());
+                            let thumb = ui.add(egui::Image::from_uri(uri).fit_to_exact_size(Vec2::splat(64.0)).sense(egui::Sense::click()));
+                            if thumb.clicked() {
+                                self.open_file(&image.path);
+                            }
+                            ui.vertical(|ui| {
+                                ui.label(image.path.display().to_string());
+                                ui.weak(format!(
+                                    "{} × {}, {}{}",
+                                    image.hashes.width,
+                                    image.hashes.height,
+                                    image.size.map(format_size).unwrap_or_default(),
+                                    if image.offline { ", drive offline" } else { "" },
+                                ));
+                            });
+                        });
+                    }
+                }
+            });
+        });
+        self.show_duplicates = open;
+        if rescan {
+            self.start_duplicate_scan();
+        }
+        if preview.is_some() {
+            self.resolve_plan = preview;
+        }
+        if undo {
+            self.undo_resolution();
+        }
+        if self.resolve_plan.is_some() {
+            self.resolve_window(ctx);
+        }
+    }
+
+    /// Shows what a resolution will move and runs it once confirmed.
+    fn resolve_window(&mut self, ctx: &Context) {
+        let Some(plan) = &self.resolve_plan else {
+            return;
+        };
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new("Resolve duplicates").collapsible(false).default_width(500.0).show(ctx, |ui| {
+            ui.label(format!(
+                "Keep {} files and move {} to {}.",
+                plan.keep.len(),
+                plan.remove.len(),
+                plan.destination.label()
+            ));
+            ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                for path in &plan.keep {
+                    ui.label(format!("Keep  {}", path.display()));
+                }
+                for path in &plan.remove {
+                    ui.colored_label(egui::Color32::LIGHT_RED, format!("Move  {}", path.display()));
+                }
+            });
+            ui.weak("Moves can be undone from the Duplicates window.");
+            ui.horizontal(|ui| {
+                if ui.add_enabled(!plan.remove.is_empty(), egui::Button::new(format!("Move {} files", plan.remove.len()))).clicked() {
+                    confirmed = true;
+                }
+                if ui.button("Cancel").clicked() {
+                    cancelled = true;
+                }
+            });
+        });
+        match self.resolve_plan.take() {
+            Some(plan) if confirmed => self.resolve_duplicates(plan),
+            plan if !cancelled => self.resolve_plan = plan,
+            _ => {}
+        }
+    }
+
+    fn resolve_duplicates(&mut self, plan: ResolvePlan) {
+        match file_ops::execute(&self.conn, &plan) {
+            Ok((moved, errors)) => {
+                self.logs.push(format!("Moved {} duplicates to {}.", moved, plan.destination.label()));
+                self.logs.extend(errors.into_iter().map(|e| format!("Could not move {}", e)));
+            }
+            Err(e) => self.logs.push(format!("DB Update Error: {}", e)),
+        }
+        // Drop whatever is gone from the groups and the results
+        for group in &mut self.duplicate_groups {
+            group.images.retain(|image| image.offline || image.path.exists());
+        }
+        self.duplicate_groups.retain(|group| group.images.len() > 1);
+        self.results.retain(|hit| hit.offline || hit.path.exists());
+        self.reload_undoable();
+    }
+
+    fn undo_resolution(&mut self) {
+        match file_ops::undo_last(&self.conn) {
+            Ok((restored, errors)) => {
+                self.logs.push(format!("Restored {} files.", restored));
+                self.logs.extend(errors.into_iter().map(|e| format!("Could not restore {}", e)));
+            }
+            Err(e) => self.logs.push(format!("DB Update Error: {}", e)),
+        }
+        self.reload_undoable();
+    }
+
+    fn reload_undoable(&mut self) {
+        self.undoable = db::get_last_file_ops(&self.conn).unwrap_or_default();
+    }
+
+    fn reload_people(&mut self) {
+        match db::get_people(&self.conn) {
+            Ok(people) => self.people = people,
+            Err(e) => self.logs.push(format!("Failed to load people: {}", e)),
+        }
+    }
+
+    fn people_window(&mut self, ctx: &Context) {
+        let mut open = self.show_people;
+        let mut naming = None;
+        egui::Window::new("People").open(&mut open).default_width(500.0).show(ctx, |ui| {
+            if self.people.is_empty() {
+                ui.label("No faces yet. Install the face models and tag a library.");
+                return;
+            }
+            ScrollArea::vertical().max_height(500.0).show(ui, |ui| {
+                for person in &self.people {
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        for sample in &person.samples {
+                            let source = sample.hash.as_deref().and_then(thumbnails::cached);
+                            let uri = format!("file://{}", source.as_deref().unwrap_or(&sample.path).display());
+                            let b = sample.bbox;
+                            let uv = egui::Rect::from_min_size(egui::pos2(b.x, b.y), egui::vec2(b.w, b.h));
+                            let face = ui.add(egui::Image::from_uri(uri).uv(uv).fit_to_exact_size(Vec2::splat(48.0)).sense(egui::Sense::click()));
+                            if face.clicked() {
+                                self.open_file(&sample.path);
+                            }
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        let name = self.person_names.entry(person.id)
+                            .or_insert_with(|| person.name.clone().unwrap_or_default());
+                        ui.add(egui::TextEdit::singleline(name).hint_text("Who is this?").desired_width(150.0));
+                        let changed = !name.trim().is_empty() && person.name.as_deref() != Some(name.trim());
+                        if ui.add_enabled(changed && !self.is_processing, egui::Button::new("Name")).clicked() {
+                            naming = Some((person.id, name.trim().to_string()));
+                        }
+                        ui.weak(format!("{} photos", person.faces));
+                    });
+                }
+            });
+        });
+        self.show_people = open;
+
+        if let Some((person_id, name)) = naming {
+            self.is_processing = true;
+            self.progress = (0, 0);
+            self.logs.push(format!("Naming person '{}'...", name));
+            self.person_names.remove(&person_id);
+            let sender = self.sender.clone();
+            thread::spawn(move || {
+                processing::run_person_naming(person_id, name, sender);
+            });
+        }
+    }
+
+    /// The image with its tags, and the detector's boxes drawn over it.
+    fn details_window(&mut self, ctx: &Context) {
+        let Some(details) = &self.details else {
+            return;
+        };
+        let mut open = true;
+        let mut open_file = false;
+        let title = details.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        egui::Window::new(title).id(egui::Id::new("details")).open(&mut open).default_width(660.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Open").clicked() {
+                    open_file = true;
+                }
+                if !details.detections.is_empty() {
+                    ui.checkbox(&mut self.show_boxes, "Show boxes");
+                }
+            });
+            if details.offline {
+                ui.label("Drive offline");
+            } else {
+                let uri = format!("file://{}", details.path.display());
+                let image = ui.add(egui::Image::from_uri(uri).max_size(egui::vec2(640.0, 480.0)));
+                if self.show_boxes {
+                    let rect = image.rect;
+                    let painter = ui.painter_at(rect);
+                    let color = egui::Color32::from_rgb(255, 200, 0);
+                    for d in &details.detections {
+                        let b = d.bbox;
+                        let min = rect.min + egui::vec2(b.x * rect.width(), b.y * rect.height());
+                        let area = egui::Rect::from_min_size(min, egui::vec2(b.w * rect.width(), b.h * rect.height()));
+                        painter.rect_stroke(area, 0.0, egui::Stroke::new(2.0, color));
+                        painter.text(
+                            area.left_top(),
+                            egui::Align2::LEFT_TOP,
+                            format!("{} {:.0}%", d.label, d.score * 100.0),
+                            egui::FontId::proportional(12.0),
+                            color,
+                        );
+                    }
+                }
+            }
+            if !details.palette.is_empty() {
+                ui.horizontal(|ui| {
+                    for color in &details.palette {
+                        let [r, g, b] = color.rgb;
+                        let (rect, response) = ui.allocate_exact_size(egui::vec2(28.0, 20.0), egui::Sense::hover());
+                        ui.painter().rect_filled(rect, 2.0, egui::Color32::from_rgb(r, g, b));
+                        response.on_hover_text(format!("{} {:.0}%", colors::to_hex(color.rgb), color.share * 100.0));
+                    }
+                });
+            }
+            for tag in &details.tags {
+                ui.weak(format!("{} {:.0}% ({})", tag.label(), tag.confidence * 100.0, tag.source.as_str()));
+            }
+        });
+        if open_file {
+            self.open_file(&details.path);
+        }
+        if !open {
+            self.details = None;
+        }
+    }
+
     fn open_file(&self, path: &std::path::Path) {
         if let Err(e) = open::that(path) {
             eprintln!("Failed to open file: {}", e);
         }
     }
 
+    fn run_search(&mut self) {
+        self.similar_to = None;
+        let color = self.color_filter
+            .then(|| query::Expr::Term(query::Term::Color(colors::to_lab(self.filter_color), self.color_tolerance)));
+        let parsed = if self.query.trim().is_empty() { Ok(None) } else { query::parse(&self.query).map(Some) };
+        match parsed {
+            Ok(expr) => {
+                let expr = match (expr, color) {
+                    (Some(expr), Some(color)) => query::Expr::And(Box::new(expr), Box::new(color)),
+                    (Some(expr), None) => expr,
+                    (None, Some(color)) => color,
+                    (None, None) => {
+                        self.results.clear();
+                        self.query_error = None;
+                        return;
+                    }
+                };
+                let expr = if self.semantic_search { expr.into_semantic() } else { expr };
+                let vector = match (expr.about_text(), &self.clip) {
+                    (None, _) => None,
+                    (Some(text), Some(clip)) => match clip.embed_text(&text) {
+                        Ok(vector) => Some(vector),
+                        Err(e) => {
+                            self.logs.push(format!("Semantic search failed: {}", e));
+                            return;
+                        }
+                    },
+                    (Some(_), None) => {
+                        self.query_error = Some("Semantic search needs the CLIP model, see Models".to_string());
+                        return;
+                    }
+                };
+                let semantic = vector.as_deref().map(|vector| db::Semantic { model: clip::CLIP_MODEL, vector });
+                self.query_error = None;
+                match db::search_images(&self.conn, &expr, self.min_confidence, self.search_root, semantic.as_ref()) {
+                    Ok(results) => self.results = results,
+                    Err(e) => self.logs.push(format!("Search failed: {}", e)),
+                }
+                self.sort_results();
+            }
+            Err(e) => self.query_error = Some(e.to_string()),
+        }
+    }
+
+    /// Replaces the results with images that look like `path`.
+    fn find_similar(&mut self, path: std::path::PathBuf) {
+        match db::similar_images(&self.conn, &path, clip::CLIP_MODEL, self.min_similarity, self.search_root) {
+            Ok(Some(results)) => {
+                self.results = results;
+                self.similar_to = Some(path);
+                self.sort_results();
+            }
+            Ok(None) => self.logs.push(format!(
+                "{} has no embedding yet. Rescan it with CLIP installed or a classifier that sets embedding_output.",
+                path.display()
+            )),
+            Err(e) => self.logs.push(format!("Similarity search failed: {}", e)),
+        }
+    }
+
+    fn sort_results(&mut self) {
+        match self.sort_order {
+            SortOrder::Relevance => self.results.sort_by(|a, b| match (a.similarity, b.similarity) {
+                (Some(a), Some(b)) => b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal),
+                _ => a.rank.partial_cmp(&b.rank).unwrap_or(std::cmp::Ordering::Equal),
+            }),
+            SortOrder::Confidence => self.results.sort_by(|a, b| {
+                b.confidence.partial_cmp(&a.confidence).unwrap_or(std::cmp::Ordering::Equal)
+            }),
+            SortOrder::Name => self.results.sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name())),
+        }
+    }
+
     fn configure_styles(ctx: &Context) {
         let mut visuals = egui::Visuals::dark();
         visuals.widgets.noninteractive.rounding = egui::Rounding::same(8.0);
@@ -99,18 +853,23 @@ impl ImageTagger {
         ctx.set_style(style);
     }
 
-    fn start_tagging(&mut self) {
+    fn start_tagging(&mut self, roots: Vec<db::LibraryRoot>) {
         if self.is_processing {
             return;
         }
         
         // Basic validation
-        if self.folder.is_empty() {
-            self.logs.push("Please select a folder first.".to_string());
+        if roots.is_empty() {
+            self.logs.push("Please add a folder first.".to_string());
             return;
         }
 
-        if let Some(session) = &self.session {
+        let taggers: Vec<Arc<dyn Tagger>> = self.taggers.iter()
+            .filter(|t| t.is_available() && !self.disabled_taggers.contains(t.name()))
+            .cloned()
+            .collect();
+
+        if !taggers.is_empty() {
             self.is_processing = true;
             self.progress = (0, 0);
             self.logs.push("Starting tagging process...".to_string());
@@ -119,21 +878,38 @@ impl ImageTagger {
             self.cancellation_token.store(false, Ordering::Relaxed);
             let token = self.cancellation_token.clone();
 
-            let session = session.clone();
-            let labels = self.labels.clone();
-            let folder = self.folder.clone();
             let sender = self.sender.clone();
-            let ocr = self.ocr.clone();
+            let config = self.tagging_config.clone();
 
             thread::spawn(move || {
-                processing::run_tagging_process(folder, session, labels, ocr, sender, token);
+                processing::run_tagging_process(roots, taggers, config, sender, token);
             });
         } else {
-            self.logs.push("Model session not initialized.".to_string());
+            self.logs.push("No taggers enabled. Check the models and Settings.".to_string());
         }
     }
 }
 
+/// Short "how long ago" for a unix timestamp.
+fn format_age(timestamp: i64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let secs = (now - timestamp).max(0);
+    match secs {
+        0..=59 => "scanned just now".to_string(),
+        60..=3599 => format!("scanned {} min ago", secs / 60),
+        3600..=86_399 => format!("scanned {} h ago", secs / 3600),
+        _ => format!("scanned {} days ago", secs / 86_400),
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.0} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
 impl App for ImageTagger {
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
         // Handle messages
@@ -141,7 +917,37 @@ impl App for ImageTagger {
             match msg {
                 AppMessage::Log(s) => self.logs.push(s),
                 AppMessage::Progress(curr, total) => self.progress = (curr, total),
-                AppMessage::Finished => self.is_processing = false,
+                AppMessage::Finished => {
+                    self.is_processing = false;
+                    self.reload_roots();
+                    if self.show_people {
+                        self.reload_people();
+                    }
+                }
+                AppMessage::StaleEntries(entries) => {
+                    self.maintenance_running = false;
+                    if !entries.is_empty() {
+                        self.logs.push(format!("{} indexed files are missing. Review them under Library.", entries.len()));
+                    }
+                    // Deleted files start selected. Files on unmounted drives
+                    // do not, so one click cannot drop a whole drive's index.
+                    self.stale_entries = entries.into_iter().map(|e| {
+                        let selected = !e.offline;
+                        (e, selected)
+                    }).collect();
+                }
+                AppMessage::ModelHealth(statuses) => {
+                    self.model_check_running = false;
+                    let broken = statuses.iter().filter(|s| !s.is_ok()).count();
+                    if broken > 0 {
+                        self.logs.push(format!("{} models have problems. See Models in the sidebar.", broken));
+                    }
+                    self.model_health = statuses;
+                }
+                AppMessage::Duplicates(groups) => {
+                    self.duplicates_running = false;
+                    self.duplicate_groups = groups;
+                }
             }
         }
         
@@ -152,13 +958,50 @@ impl App for ImageTagger {
             ui.separator();
             
             ui.group(|ui| {
-                ui.heading("Tagging");
-                ui.label("Folder path:");
-                ui.horizontal(|ui| {
-                    ui.text_edit_singleline(&mut self.folder);
-                    if ui.button("Browse...").clicked() {
-                        if let Some(path) = rfd::FileDialog::new().pick_folder() {
-                            self.folder = path.display().to_string();
+                ui.heading("Libraries");
+                self.roots_list(ui);
+                if ui.button("Add folder...").clicked()
+                    && let Some(path) = rfd::FileDialog::new().pick_folder()
+                {
+                    if let Err(e) = db::add_root(&self.conn, &path) {
+                        self.logs.push(format!("Failed to add folder: {}", e));
+                    }
+                    self.reload_roots();
+                }
+                ui.separator();
+                ui.collapsing("Settings", |ui| {
+                    ui.add(egui::Slider::new(&mut self.tagging_config.min_probability, 0.0..=1.0).text("Min probability"));
+                    ui.add(egui::Slider::new(&mut self.tagging_config.max_tags, 1..=20).text("Max tags"));
+                    ui.add(egui::Slider::new(&mut self.tagging_config.cumulative_cutoff, 0.0..=1.0).text("Cumulative cutoff"));
+                    ui.add(egui::Slider::new(&mut self.tagging_config.decode_workers, 1..=16).text("Decode threads"));
+                    ui.add(egui::Slider::new(&mut self.tagging_config.inference_workers, 1..=8).text("Inference threads"));
+                    ui.add(egui::Slider::new(&mut self.tagging_config.batch_size, 1..=64).text("Batch size"))
+                        .on_hover_text("Images per model run. Models with a fixed input shape always use 1.");
+                    ui.label("Taggers:");
+                    for tagger in &self.taggers {
+                        let name = tagger.name().to_string();
+                        let mut enabled = !self.disabled_taggers.contains(&name);
+                        let label = format!("{} (v{})", name, tagger.version());
+                        let resp = ui.add_enabled(tagger.is_available(), egui::Checkbox::new(&mut enabled, label))
+                            .on_disabled_hover_text("Model files not found");
+                        if let Some(default_crop) = tagger.default_crop() {
+                            let crop = self.tagging_config.crop_modes.entry(name.clone()).or_insert(default_crop);
+                            egui::ComboBox::from_id_source(("crop_mode", &name))
+                                .selected_text(crop.label())
+                                .show_ui(ui, |ui| {
+                                    for mode in ml::CropMode::ALL {
+                                        ui.selectable_value(crop, mode, mode.label());
+                                    }
+                                })
+                                .response
+                                .on_hover_text("More crops average out framing but take longer");
+                        }
+                        if resp.changed() {
+                            if enabled {
+                                self.disabled_taggers.remove(&name);
+                            } else {
+                                self.disabled_taggers.insert(name);
+                            }
                         }
                     }
                 });
@@ -169,8 +1012,8 @@ impl App for ImageTagger {
                         self.logs.push("Cancelling...".to_string());
                     }
                 } else {
-                     if ui.button("Tag Images").clicked() {
-                        self.start_tagging();
+                     if ui.button("Scan All").clicked() {
+                        self.start_tagging(self.roots.clone());
                     }
                 }
                 
@@ -188,6 +1031,17 @@ impl App for ImageTagger {
 
             ui.add_space(20.0);
 
+            ui.group(|ui| {
+                ui.heading("Models");
+                self.models_list(ui);
+                if !self.model_check_running && ui.button("Check again").clicked() {
+                    self.start_model_check();
+                }
+                ui.weak("Taggers load at startup; restart after fixing a model.");
+            });
+
+            ui.add_space(20.0);
+
             ui.group(|ui| {
                 ui.heading("Manage Tags");
                 ui.horizontal(|ui| {
@@ -199,20 +1053,63 @@ impl App for ImageTagger {
                     ui.text_edit_singleline(&mut self.rename_new);
                 });
                 
-                if ui.button("Rename Globally").clicked() {
-                     if !self.rename_old.is_empty() && !self.rename_new.is_empty() && !self.is_processing {
-                        self.is_processing = true;
-                        self.progress = (0, 0);
-                        self.logs.push(format!("Renaming '{}' to '{}'...", self.rename_old, self.rename_new));
-                        
-                        let sender = self.sender.clone();
-                        let old_tag = self.rename_old.clone();
-                        let new_tag = self.rename_new.clone();
-                        
-                        thread::spawn(move || {
-                            processing::run_renaming_process(old_tag, new_tag, sender);
-                        });
-                     }
+                if ui.button("Rename Globally").clicked()
+                    && !self.rename_old.is_empty() && !self.rename_new.is_empty() && !self.is_processing
+                {
+                    self.is_processing = true;
+                    self.progress = (0, 0);
+                    self.logs.push(format!("Renaming '{}' to '{}'...", self.rename_old, self.rename_new));
+                    
+                    let sender = self.sender.clone();
+                    let old_tag = self.rename_old.clone();
+                    let new_tag = self.rename_new.clone();
+                    
+                    thread::spawn(move || {
+                        processing::run_renaming_process(old_tag, new_tag, sender);
+                    });
+                }
+            });
+
+            ui.add_space(20.0);
+
+            ui.group(|ui| {
+                ui.heading("Library");
+                ui.horizontal(|ui| {
+                    if self.maintenance_running {
+                        ui.spinner();
+                        ui.label("Checking files...");
+                    } else if ui.button("Check for missing files").clicked() {
+                        self.start_maintenance_scan();
+                        self.show_maintenance = true;
+                    }
+                });
+                if !self.stale_entries.is_empty() && ui.button(format!("Review {} missing", self.stale_entries.len())).clicked() {
+                    self.show_maintenance = true;
+                }
+                ui.horizontal(|ui| {
+                    if self.duplicates_running {
+                        ui.spinner();
+                        ui.label("Finding duplicates...");
+                    } else if ui.button("Find duplicates").on_hover_text("In the search scope").clicked() {
+                        self.start_duplicate_scan();
+                        self.show_duplicates = true;
+                    }
+                });
+                if ui.button("People").on_hover_text("Faces found while tagging, grouped by person").clicked() {
+                    self.reload_people();
+                    self.show_people = true;
+                }
+                ui.separator();
+                ui.weak(format!("Catalog: {}", db::db_path().display()));
+                let idle = !self.is_processing && !self.maintenance_running;
+                if ui.add_enabled(idle, egui::Button::new("Open catalog...")).clicked() {
+                    let picked = rfd::FileDialog::new()
+                        .add_filter("Local Lens catalog", &["db"])
+                        .set_file_name("image_tags.db")
+                        .save_file();
+                    if let Some(path) = picked {
+                        self.open_catalog(path);
+                    }
                 }
             });
 
@@ -221,10 +1118,46 @@ impl App for ImageTagger {
             ui.group(|ui| {
                 ui.heading("Search");
                 ui.label("Search query:");
-                ui.text_edit_singleline(&mut self.query);
+                ui.weak("e.g. dog AND beach NOT night, tag:receipt text:\"total due\", ext:png conf>0.8");
+                let query_resp = ui.text_edit_singleline(&mut self.query);
+                let scope = self.search_root
+                    .and_then(|id| self.roots.iter().find(|r| r.id == id))
+                    .map(|r| r.path.display().to_string())
+                    .unwrap_or_else(|| "All libraries".to_string());
+                egui::ComboBox::from_label("Scope")
+                    .selected_text(scope)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.search_root, None, "All libraries");
+                        for root in &self.roots {
+                            ui.selectable_value(&mut self.search_root, Some(root.id), root.path.display().to_string());
+                        }
+                    });
+                if query_resp.changed() {
+                    // Validate as you type, but only search on demand
+                    self.query_error = match query::parse(&self.query) {
+                        Err(e) if !self.query.trim().is_empty() => Some(e.to_string()),
+                        _ => None,
+                    };
+                }
+                if let Some(err) = &self.query_error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, err);
+                }
+                ui.add(egui::Slider::new(&mut self.min_confidence, 0.0..=1.0).text("Min confidence"));
+                ui.add_enabled(self.clip.is_some(), egui::Checkbox::new(&mut self.semantic_search, "Semantic"))
+                    .on_hover_text("Match words by what the image shows, like about:\"...\"")
+                    .on_disabled_hover_text("CLIP model files not found");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.color_filter, "Color");
+                    ui.add_enabled_ui(self.color_filter, |ui| ui.color_edit_button_srgb(&mut self.filter_color));
+                });
+                if self.color_filter {
+                    ui.add(egui::Slider::new(&mut self.color_tolerance, 5.0..=60.0).text("Tolerance"))
+                        .on_hover_text("How far a palette color may be from the picked one, in ΔE");
+                }
                 ui.add_space(5.0);
-                if ui.button("Search").clicked() {
-                     self.results = db::search_images(&self.conn, &self.query).unwrap_or_default();
+                let submitted = query_resp.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if ui.button("Search").clicked() || submitted {
+                    self.run_search();
                 }
             });
         });
@@ -246,17 +1179,52 @@ impl App for ImageTagger {
                     ui.label("No results found. Start by tagging a folder or searching.");
                 });
             } else {
-                ui.heading(format!("Results ({})", self.results.len()));
+                ui.horizontal(|ui| {
+                    ui.heading(format!("Results ({})", self.results.len()));
+                    let before = self.sort_order;
+                    egui::ComboBox::from_label("Sort by")
+                        .selected_text(match self.sort_order {
+                            SortOrder::Relevance => "Relevance",
+                            SortOrder::Confidence => "Confidence",
+                            SortOrder::Name => "Name",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut self.sort_order, SortOrder::Relevance, "Relevance");
+                            ui.selectable_value(&mut self.sort_order, SortOrder::Confidence, "Confidence");
+                            ui.selectable_value(&mut self.sort_order, SortOrder::Name, "Name");
+                        });
+                    if self.sort_order != before {
+                        self.sort_results();
+                    }
+                });
+                if let Some(source) = self.similar_to.clone() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Like {}", source.file_name().unwrap_or_default().to_string_lossy()));
+                        let slider = ui.add(egui::Slider::new(&mut self.min_similarity, 0.5..=1.0).text("Min similarity"));
+                        if slider.drag_stopped() || (slider.changed() && !slider.dragged()) {
+                            self.find_similar(source);
+                        }
+                    });
+                }
                 ui.separator();
                 
+                let mut more_like = None;
+                let mut show_details = None;
                 ScrollArea::vertical().show(ui, |ui| {
                     ui.horizontal_wrapped(|ui| {
-                        for path in &self.results {
+                        for hit in &self.results {
+                            let path = &hit.path;
                             ui.allocate_ui(Vec2::new(160.0, 200.0), |ui| {
                                 ui.vertical_centered(|ui| {
-                                     let uri = format!("file://{}", path.display());
-                                     // Interactive image
-                                     let img_resp = ui.add(egui::Image::from_uri(uri).fit_to_exact_size(Vec2::splat(150.0)).sense(egui::Sense::click()));
+                                     let img_resp = if hit.offline {
+                                         ui.add_sized(Vec2::splat(150.0), egui::Label::new("Drive offline").sense(egui::Sense::hover()))
+                                     } else {
+                                         // Fall back to the full file for images tagged before thumbnails
+                                         let source = hit.hash.as_deref().and_then(thumbnails::cached);
+                                         let uri = format!("file://{}", source.as_deref().unwrap_or(path).display());
+                                         // Interactive image
+                                         ui.add(egui::Image::from_uri(uri).fit_to_exact_size(Vec2::splat(150.0)).sense(egui::Sense::click()))
+                                     };
                                      
                                      if img_resp.clicked() {
                                          self.open_file(path);
@@ -264,18 +1232,65 @@ impl App for ImageTagger {
                                      if img_resp.hovered() {
                                          ctx.set_cursor_icon(egui::CursorIcon::PointingHand);
                                      }
+                                     img_resp.context_menu(|ui| {
+                                         if ui.button("More like this").clicked() {
+                                             more_like = Some(path.clone());
+                                             ui.close_menu();
+                                         }
+                                         if ui.button("Details").clicked() {
+                                             show_details = Some((path.clone(), hit.offline));
+                                             ui.close_menu();
+                                         }
+                                     });
+                                     img_resp.on_hover_ui(|ui| {
+                                         for tag in &hit.tags {
+                                             ui.label(format!(
+                                                 "{} {:.0}% ({}{})",
+                                                 tag.label(),
+                                                 tag.confidence * 100.0,
+                                                 tag.source.as_str(),
+                                                 tag.model.as_deref().map(|m| format!(", {}", m)).unwrap_or_default(),
+                                             ));
+                                         }
+                                     });
                                      
                                      ui.label(path.file_name().unwrap_or_default().to_string_lossy());
+                                     match hit.similarity {
+                                         Some(similarity) => ui.weak(format!("{:.2} similar", similarity)),
+                                         None => ui.weak(format!("{:.0}%", hit.confidence * 100.0)),
+                                     };
                                 });
                             });
                         }
                     });
                 });
+                if let Some(path) = more_like {
+                    self.find_similar(path);
+                }
+                if let Some((path, offline)) = show_details {
+                    let tags = db::get_tags(&self.conn, &path).unwrap_or_default();
+                    let detections = db::get_detections(&self.conn, &path).unwrap_or_default();
+                    let palette = db::get_palette(&self.conn, &path).unwrap_or_default();
+                    self.details = Some(Details { path, offline, tags, detections, palette });
+                }
             }
         });
 
+        if self.show_maintenance {
+            self.maintenance_window(ctx);
+        }
+        if self.show_duplicates {
+            self.duplicates_window(ctx);
+        }
+        if self.show_people {
+            self.people_window(ctx);
+        }
+        if self.details.is_some() {
+            self.details_window(ctx);
+        }
+
         // Repaint if processing to show progress smoothly
-        if self.is_processing {
+        if self.is_processing || self.maintenance_running || self.model_check_running || self.duplicates_running {
             ctx.request_repaint();
         }
     }
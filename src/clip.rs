@@ -0,0 +1,161 @@
+use image::DynamicImage;
+use ort::session::Session;
+use ort::value::{DynValue, Tensor};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokenizers::Tokenizer;
+
+use crate::ml::{self, TaggingConfig};
+use crate::tagger::{DecodedImage, Tagger, TaggerOutput};
+use crate::tags::{ScoredTag, TagSource};
+
+/// Identifier recorded with every embedding the model produces.
+pub const CLIP_MODEL: &str = "clip-vit-b32";
+
+/// Image encoder, text encoder and tokenizer, e.g. as exported by
+/// `optimum-cli export onnx` and split into the two towers.
+pub const CLIP_FILES: [&str; 3] = ["clip-image.onnx", "clip-text.onnx", "clip-tokenizer.json"];
+
+/// Tokens per text input.
+const CONTEXT_LENGTH: usize = 77;
+const INPUT_SIDE: u32 = 224;
+const MEAN: [f32; 3] = [0.481_454_66, 0.457_827_5, 0.408_210_73];
+const STD: [f32; 3] = [0.268_629_54, 0.261_302_6, 0.275_777_1];
+
+/// CLIP image and text encoders. Images get an embedding while tagging; the
+/// text encoder turns a search into a vector in the same space.
+pub struct ClipModel {
+    image_path: PathBuf,
+    /// Idle image sessions, shared by the inference workers.
+    image_sessions: Mutex<Vec<Session>>,
+    text_session: Mutex<Session>,
+    tokenizer: Tokenizer,
+}
+
+impl ClipModel {
+    pub fn load() -> anyhow::Result<Self> {
+        let [image, text, tokenizer] = CLIP_FILES.map(|file| {
+            ml::find_model_file(file).ok_or_else(|| anyhow::anyhow!("{} not found", file))
+        });
+        let image_path = image?;
+        let image_session = Session::builder()?.commit_from_file(&image_path)?;
+        let text_session = Session::builder()?.commit_from_file(text?)?;
+        let tokenizer = Tokenizer::from_file(tokenizer?).map_err(|e| anyhow::anyhow!("Tokenizer: {}", e))?;
+        Ok(Self {
+            image_path,
+            image_sessions: Mutex::new(vec![image_session]),
+            text_session: Mutex::new(text_session),
+            tokenizer,
+        })
+    }
+
+    /// Unit-length embedding of a natural-language query.
+    pub fn embed_text(&self, text: &str) -> anyhow::Result<Vec<f32>> {
+        let encoding = self.tokenizer.encode(text, true).map_err(|e| anyhow::anyhow!("Tokenizer: {}", e))?;
+        let mut ids: Vec<i64> = encoding.get_ids().iter().map(|&id| id as i64).collect();
+        ids.truncate(CONTEXT_LENGTH);
+        let mut mask = vec![1i64; ids.len()];
+        ids.resize(CONTEXT_LENGTH, 0);
+        mask.resize(CONTEXT_LENGTH, 0);
+
+        let mut session = self.text_session.lock().map_err(|_| anyhow::anyhow!("Failed to lock session"))?;
+        let names: Vec<String> = session.inputs().iter().map(|input| input.name().to_string()).collect();
+        let mut inputs: Vec<(String, DynValue)> = Vec::new();
+        for name in names {
+            let values = if name.contains("mask") { mask.clone() } else { ids.clone() };
+            let tensor = Tensor::from_array((vec![1, CONTEXT_LENGTH], values.into_boxed_slice()))?;
+            inputs.push((name, tensor.into_dyn()));
+        }
+        let outputs = session.run(inputs)?;
+        let output = outputs.get("text_embeds").unwrap_or(&outputs[0]);
+        let (_shape, data) = output.try_extract_tensor::<f32>()?;
+        Ok(ml::normalized(data))
+    }
+
+    fn embed_images(&self, images: &[&DecodedImage]) -> anyhow::Result<Vec<Vec<f32>>> {
+        if images.is_empty() {
+            return Ok(Vec::new());
+        }
+        let side = INPUT_SIDE as usize;
+        let mut input = Vec::with_capacity(images.len() * 3 * side * side);
+        for image in images {
+            input.extend(preprocess(&image.preview));
+        }
+        let tensor = Tensor::from_array((vec![images.len(), 3, side, side], input.into_boxed_slice()))?;
+
+        let idle = self.image_sessions.lock()
+            .map_err(|_| anyhow::anyhow!("Failed to lock session pool"))?
+            .pop();
+        let mut session = match idle {
+            Some(session) => session,
+            None => Session::builder()?.commit_from_file(&self.image_path)?,
+        };
+        let embeddings = run_image_session(&mut session, tensor, images.len());
+        if let Ok(mut idle) = self.image_sessions.lock() {
+            idle.push(session);
+        }
+        embeddings
+    }
+}
+
+fn run_image_session(session: &mut Session, tensor: Tensor<f32>, count: usize) -> anyhow::Result<Vec<Vec<f32>>> {
+    let outputs = session.run(ort::inputs![tensor])?;
+    let output = outputs.get("image_embeds").unwrap_or(&outputs[0]);
+    let (_shape, data) = output.try_extract_tensor::<f32>()?;
+    let dim = (data.len() / count).max(1);
+    Ok(data.chunks(dim).map(ml::normalized).collect())
+}
+
+impl Tagger for ClipModel {
+    fn name(&self) -> &str {
+        CLIP_MODEL
+    }
+
+    fn version(&self) -> &str {
+        "1"
+    }
+
+    fn source(&self) -> TagSource {
+        TagSource::Classifier
+    }
+
+    /// CLIP adds no tags of its own, only the embedding.
+    fn tag(&self, _image: &DecodedImage, _config: &TaggingConfig) -> anyhow::Result<Vec<ScoredTag>> {
+        Ok(Vec::new())
+    }
+
+    fn analyze(&self, image: &DecodedImage, config: &TaggingConfig) -> anyhow::Result<TaggerOutput> {
+        self.tag_batch(&[image], config).pop().unwrap_or_else(|| Ok(TaggerOutput::default()))
+    }
+
+    fn tag_batch(&self, images: &[&DecodedImage], _config: &TaggingConfig) -> Vec<anyhow::Result<TaggerOutput>> {
+        let embedded = |embedding| TaggerOutput { embedding: Some(embedding), ..Default::default() };
+        match self.embed_images(images) {
+            Ok(embeddings) => embeddings.into_iter().map(|embedding| Ok(embedded(embedding))).collect(),
+            // Fixed-batch exports only take one image per run
+            Err(_) if images.len() > 1 => images.iter()
+                .map(|&image| -> anyhow::Result<TaggerOutput> {
+                    let embedding = self.embed_images(&[image])?.pop();
+                    embedding.map(embedded).ok_or_else(|| anyhow::anyhow!("No embedding returned"))
+                })
+                .collect(),
+            Err(e) => vec![Err(e)],
+        }
+    }
+}
+
+/// Shorter side to 224 with bicubic filtering, centre crop, CLIP mean and std.
+fn preprocess(img: &DynamicImage) -> Vec<f32> {
+    let (w, h) = (img.width(), img.height());
+    let scale = INPUT_SIDE as f32 / w.min(h).max(1) as f32;
+    let w = ((w as f32 * scale).round() as u32).max(INPUT_SIDE);
+    let h = ((h as f32 * scale).round() as u32).max(INPUT_SIDE);
+    let resized = img.resize_exact(w, h, image::imageops::FilterType::CatmullRom);
+    let rgb = resized.crop_imm((w - INPUT_SIDE) / 2, (h - INPUT_SIDE) / 2, INPUT_SIDE, INPUT_SIDE).to_rgb8();
+
+    let mut input = Vec::with_capacity(rgb.len());
+    for c in 0..3 {
+        input.extend(rgb.pixels().map(|pixel| (pixel[c] as f32 / 255.0 - MEAN[c]) / STD[c]));
+    }
+    input
+}
@@ -0,0 +1,218 @@
+use image::DynamicImage;
+use image::imageops::FilterType;
+
+use crate::ml::TaggingConfig;
+use crate::tagger::{DecodedImage, Tagger, TaggerOutput};
+use crate::tags::{ScoredTag, TagSource};
+
+/// Identifier recorded with the color tags and palettes.
+pub const COLOR_MODEL: &str = "palette";
+
+const PALETTE_SIZE: usize = 5;
+/// Images are sampled at this size; palettes barely change above it.
+const SAMPLE_SIDE: u32 = 64;
+const ITERATIONS: usize = 12;
+/// Share of the image a named color needs to become a tag.
+const MIN_TAG_SHARE: f32 = 0.1;
+/// Share of the image a palette color needs to match a color search, so
+/// specks do not count.
+pub const MIN_MATCH_SHARE: f32 = 0.05;
+/// Default distance for color searches, in CIE76 ΔE. Around 2 is the smallest
+/// difference people notice; 20 still reads as "the same color".
+pub const DEFAULT_TOLERANCE: f32 = 20.0;
+
+/// CIELAB, D65 white point.
+pub type Lab = [f32; 3];
+
+/// Reference colors for the color tags and `color:<name>` searches.
+const NAMED_COLORS: [(&str, [u8; 3]); 13] = [
+    ("red", [200, 30, 40]),
+    ("orange", [240, 130, 30]),
+    ("yellow", [245, 215, 50]),
+    ("green", [60, 150, 60]),
+    ("teal", [20, 130, 130]),
+    ("blue", [40, 90, 200]),
+    ("purple", [120, 60, 160]),
+    ("pink", [240, 140, 180]),
+    ("brown", [110, 70, 40]),
+    ("beige", [220, 200, 165]),
+    ("black", [20, 20, 20]),
+    ("gray", [128, 128, 128]),
+    ("white", [245, 245, 245]),
+];
+
+/// One color of an image's palette.
+#[derive(Clone, Debug)]
+pub struct PaletteColor {
+    /// Average sRGB of the pixels in the cluster.
+    pub rgb: [u8; 3],
+    pub lab: Lab,
+    /// Fraction of the image, the palette adds up to 1.
+    pub share: f32,
+}
+
+/// Pure-Rust palette extraction: k-means over the pixels in Lab space, where
+/// distances follow perceived difference, and a tag for each named color that
+/// covers enough of the image.
+pub struct ColorAnalyzer;
+
+impl Tagger for ColorAnalyzer {
+    fn name(&self) -> &str {
+        COLOR_MODEL
+    }
+
+    fn version(&self) -> &str {
+        "1"
+    }
+
+    fn source(&self) -> TagSource {
+        TagSource::Color
+    }
+
+    fn tag(&self, image: &DecodedImage, config: &TaggingConfig) -> anyhow::Result<Vec<ScoredTag>> {
+        Ok(self.analyze(image, config)?.tags)
+    }
+
+    fn analyze(&self, image: &DecodedImage, _config: &TaggingConfig) -> anyhow::Result<TaggerOutput> {
+        let palette = palette(&image.preview);
+        let mut shares: Vec<(&str, f32)> = Vec::new();
+        for color in &palette {
+            let name = nearest_name(color.lab);
+            match shares.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, share)) => *share += color.share,
+                None => shares.push((name, color.share)),
+            }
+        }
+        let tags = shares.into_iter()
+            .filter(|&(_, share)| share >= MIN_TAG_SHARE)
+            .map(|(name, share)| ScoredTag::new(name, share.min(1.0), TagSource::Color, Some(COLOR_MODEL)))
+            .collect();
+        Ok(TaggerOutput { tags, palette: Some(palette), ..Default::default() })
+    }
+}
+
+/// Up to `PALETTE_SIZE` dominant colors, largest share first.
+pub fn palette(image: &DynamicImage) -> Vec<PaletteColor> {
+    let rgb = image.resize(SAMPLE_SIDE, SAMPLE_SIDE, FilterType::Triangle).to_rgb8();
+    let pixels: Vec<([u8; 3], Lab)> = rgb.pixels().map(|p| (p.0, to_lab(p.0))).collect();
+    if pixels.is_empty() {
+        return Vec::new();
+    }
+
+    // Farthest-first seeding keeps runs repeatable and spreads the centers
+    let mut centers: Vec<Lab> = vec![mean(pixels.iter().map(|p| p.1))];
+    while centers.len() < PALETTE_SIZE {
+        let farthest = pixels.iter()
+            .map(|p| (p.1, nearest(&centers, p.1).1))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        match farthest {
+            Some((lab, distance)) if distance > 0.0 => centers.push(lab),
+            // Fewer distinct colors than the palette has room for
+            _ => break,
+        }
+    }
+
+    let mut assignment = vec![0; pixels.len()];
+    for _ in 0..ITERATIONS {
+        let mut changed = false;
+        for (slot, pixel) in assignment.iter_mut().zip(&pixels) {
+            let (k, _) = nearest(&centers, pixel.1);
+            changed |= *slot != k;
+            *slot = k;
+        }
+        for (k, center) in centers.iter_mut().enumerate() {
+            let members: Vec<Lab> = pixels.iter().zip(&assignment).filter(|&(_, &a)| a == k).map(|(p, _)| p.1).collect();
+            // An empty cluster keeps its center
+            if !members.is_empty() {
+                *center = mean(members.into_iter());
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let total = pixels.len() as f32;
+    let mut palette: Vec<PaletteColor> = centers.iter().enumerate()
+        .filter_map(|(k, &lab)| {
+            let members: Vec<[u8; 3]> = pixels.iter().zip(&assignment).filter(|&(_, &a)| a == k).map(|(p, _)| p.0).collect();
+            if members.is_empty() {
+                return None;
+            }
+            let mut sum = [0u32; 3];
+            for rgb in &members {
+                (0..3).for_each(|c| sum[c] += rgb[c] as u32);
+            }
+            let n = members.len() as u32;
+            let rgb = sum.map(|s| (s / n) as u8);
+            Some(PaletteColor { rgb, lab, share: members.len() as f32 / total })
+        })
+        .collect();
+    palette.sort_by(|a, b| b.share.partial_cmp(&a.share).unwrap_or(std::cmp::Ordering::Equal));
+    palette
+}
+
+/// Index of the closest center and the squared distance to it.
+fn nearest(centers: &[Lab], lab: Lab) -> (usize, f32) {
+    centers.iter()
+        .map(|&center| distance_squared(center, lab))
+        .enumerate()
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .unwrap_or((0, 0.0))
+}
+
+fn mean(labs: impl Iterator<Item = Lab>) -> Lab {
+    let (mut sum, mut n) = ([0.0f32; 3], 0);
+    for lab in labs {
+        (0..3).for_each(|c| sum[c] += lab[c]);
+        n += 1;
+    }
+    sum.map(|s| s / n.max(1) as f32)
+}
+
+fn distance_squared(a: Lab, b: Lab) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+/// The named color closest to `lab`.
+fn nearest_name(lab: Lab) -> &'static str {
+    NAMED_COLORS.iter()
+        .map(|&(name, rgb)| (name, distance_squared(to_lab(rgb), lab)))
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map_or("gray", |(name, _)| name)
+}
+
+/// Reference color for a name like `blue`, for `color:blue`.
+pub fn named_color(name: &str) -> Option<[u8; 3]> {
+    NAMED_COLORS.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|&(_, rgb)| rgb)
+}
+
+/// `#1e90ff` or `1e90ff`.
+pub fn parse_hex(s: &str) -> Option<[u8; 3]> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+pub fn to_hex(rgb: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
+
+/// sRGB to CIELAB.
+pub fn to_lab(rgb: [u8; 3]) -> Lab {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(rgb[0]), linear(rgb[1]), linear(rgb[2]));
+    // XYZ relative to the D65 white
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
@@ -1,42 +1,1386 @@
-use rusqlite::{Connection, Result};
-use std::path::PathBuf;
+use rusqlite::types::Value;
+use rusqlite::{Connection, OptionalExtension, Result, Transaction, params, params_from_iter};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::colors::PaletteColor;
+use crate::detection::{BoundingBox, Detection};
+use crate::faces::Face;
+use crate::ml::TaggingConfig;
+use crate::paths;
+use crate::query::{Expr, Text};
+use crate::scanner::{FileStat, ScanRules};
+use crate::tags::{ScoredTag, TagSource};
+
+type Migration = fn(&Transaction) -> Result<()>;
+
+// Each entry upgrades the schema by one version. `PRAGMA user_version` holds
+// the number of migrations already applied, so never reorder or edit these.
+const MIGRATIONS: &[Migration] = &[
+    migrate_v1_normalize_tags,
+    migrate_v2_tag_scores,
+    migrate_v3_tagging_runs,
+    migrate_v4_full_text,
+    migrate_v5_file_stats,
+    migrate_v6_offline,
+    migrate_v7_library_roots,
+    migrate_v8_embeddings,
+    migrate_v9_perceptual_hashes,
+    migrate_v10_file_ops,
+    migrate_v11_faces,
+    migrate_v12_detections,
+    migrate_v13_palettes,
+    migrate_v14_tag_models,
+    migrate_v15_moved_away,
+    migrate_v16_tag_counts,
+];
+
+const DB_FILE: &str = "image_tags.db";
+
+// Catalog chosen on the command line or in the app; `None` means the default.
+static DB_PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// `$XDG_DATA_HOME/local_lens/image_tags.db`, or the working directory if
+/// there is no home to put it in.
+pub fn default_db_path() -> PathBuf {
+    paths::data_dir().map(|dir| dir.join(DB_FILE)).unwrap_or_else(|| PathBuf::from(DB_FILE))
+}
+
+pub fn db_path() -> PathBuf {
+    DB_PATH.read().ok().and_then(|p| p.clone()).unwrap_or_else(default_db_path)
+}
+
+/// Points every later `setup_db` call, on any thread, at `path`.
+pub fn set_db_path(path: PathBuf) {
+    if let Ok(mut current) = DB_PATH.write() {
+        *current = Some(path);
+    }
+}
+
+fn catalog_setting_file() -> Option<PathBuf> {
+    paths::config_dir().map(|dir| dir.join("catalog"))
+}
+
+/// Catalog picked in the app last time, if any.
+pub fn saved_catalog() -> Option<PathBuf> {
+    let contents = fs::read_to_string(catalog_setting_file()?).ok()?;
+    let path = contents.trim();
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+pub fn save_catalog(path: &Path) -> std::io::Result<()> {
+    let Some(file) = catalog_setting_file() else {
+        return Ok(());
+    };
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(file, path.to_string_lossy().as_bytes())
+}
+
+/// Older versions kept the catalog in the working directory. Moves one found
+/// there to the default location, once, and says where it went.
+pub fn adopt_legacy_db() -> Option<PathBuf> {
+    let legacy = Path::new(DB_FILE);
+    let target = default_db_path();
+    if !legacy.is_file() || target.exists() || target == legacy {
+        return None;
+    }
+    fs::create_dir_all(target.parent()?).ok()?;
+    // rename fails across filesystems, fall back to copying
+    if fs::rename(legacy, &target).is_err() {
+        fs::copy(legacy, &target).ok()?;
+        fs::remove_file(legacy).ok();
+    }
+    Some(target)
+}
 
 pub fn setup_db() -> Result<Connection> {
-    let conn = Connection::open("image_tags.db")?;
+    let path = db_path();
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).ok();
+    }
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+/// A migrated catalog in memory, for tests.
+#[cfg(test)]
+pub fn open_in_memory() -> Result<Connection> {
+    let mut conn = Connection::open_in_memory()?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn migrate_v1_normalize_tags(tx: &Transaction) -> Result<()> {
+    // Databases created before versioning have `images (path, tags)` with a
+    // comma-joined tag string; move it aside and rebuild it below.
+    let legacy = tx
+        .prepare("SELECT 1 FROM pragma_table_info('images') WHERE name = 'tags'")?
+        .exists([])?;
+    if legacy {
+        tx.execute_batch("ALTER TABLE images RENAME TO images_legacy;")?;
+    }
+
+    tx.execute_batch(
+        "CREATE TABLE images (
+            id INTEGER PRIMARY KEY,
+            path TEXT NOT NULL UNIQUE,
+            added_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );
+        CREATE TABLE tags (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE
+        );
+        CREATE TABLE image_tags (
+            image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+            tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            added_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY (image_id, tag_id)
+        );
+        CREATE INDEX idx_image_tags_tag ON image_tags(tag_id);",
+    )?;
+
+    if legacy {
+        let rows: Vec<(String, Option<String>)> = {
+            let mut stmt = tx.prepare("SELECT path, tags FROM images_legacy")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<Result<_>>()?
+        };
+        for (path, tags_str) in rows {
+            let tags: Vec<String> = tags_str
+                .unwrap_or_default()
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+            let image_id = upsert_image(tx, &path)?;
+            for tag in tags {
+                let tag_id = upsert_tag(tx, &tag)?;
+                tx.execute(
+                    "INSERT OR IGNORE INTO image_tags (image_id, tag_id) VALUES (?1, ?2)",
+                    params![image_id, tag_id],
+                )?;
+            }
+        }
+        tx.execute_batch("DROP TABLE images_legacy;")?;
+    }
+    Ok(())
+}
+
+fn migrate_v2_tag_scores(tx: &Transaction) -> Result<()> {
+    // A tag may be produced by several sources for the same image, so the
+    // source becomes part of the key. Existing rows have unknown provenance.
+    tx.execute_batch(
+        "CREATE TABLE image_tags_v2 (
+            image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+            tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            source TEXT NOT NULL DEFAULT 'imported',
+            confidence REAL NOT NULL DEFAULT 1.0,
+            model TEXT,
+            added_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY (image_id, tag_id, source)
+        );
+        INSERT INTO image_tags_v2 (image_id, tag_id, added_at)
+            SELECT image_id, tag_id, added_at FROM image_tags;
+        DROP TABLE image_tags;
+        ALTER TABLE image_tags_v2 RENAME TO image_tags;
+        CREATE INDEX idx_image_tags_tag ON image_tags(tag_id);
+        CREATE INDEX idx_image_tags_confidence ON image_tags(confidence);",
+    )
+}
+
+fn migrate_v3_tagging_runs(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE tagging_runs (
+            id INTEGER PRIMARY KEY,
+            folder TEXT NOT NULL,
+            min_probability REAL NOT NULL,
+            max_tags INTEGER NOT NULL,
+            cumulative_cutoff REAL NOT NULL,
+            started_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            finished_at INTEGER
+        );
+        ALTER TABLE image_tags ADD COLUMN run_id INTEGER REFERENCES tagging_runs(id) ON DELETE SET NULL;",
+    )
+}
+
+fn migrate_v4_full_text(tx: &Transaction) -> Result<()> {
+    // `image_fts` mirrors `image_fts_source` one row per image (rowid = image
+    // id). Triggers rebuild an image's row whenever its tags or text change.
+    // Images indexed before OCR text was kept fall back to their OCR words.
+    tx.execute_batch(
+        "ALTER TABLE images ADD COLUMN ocr_text TEXT;
+
+        CREATE VIEW image_fts_source AS
+            SELECT i.id AS image_id,
+                COALESCE((SELECT group_concat(t.name, ' ') FROM image_tags it
+                          JOIN tags t ON t.id = it.tag_id
+                          WHERE it.image_id = i.id AND it.source != 'ocr'), '') AS tags,
+                COALESCE(i.ocr_text, (SELECT group_concat(t.name, ' ') FROM image_tags it
+                          JOIN tags t ON t.id = it.tag_id
+                          WHERE it.image_id = i.id AND it.source = 'ocr'), '') AS text
+            FROM images i;
+
+        CREATE VIRTUAL TABLE image_fts USING fts5(
+            tags, text,
+            tokenize = 'unicode61 remove_diacritics 2',
+            prefix = '2 3'
+        );
+        INSERT INTO image_fts (rowid, tags, text) SELECT image_id, tags, text FROM image_fts_source;
+
+        CREATE TRIGGER image_tags_fts_insert AFTER INSERT ON image_tags BEGIN
+            DELETE FROM image_fts WHERE rowid = NEW.image_id;
+            INSERT INTO image_fts (rowid, tags, text)
+                SELECT image_id, tags, text FROM image_fts_source WHERE image_id = NEW.image_id;
+        END;
+        CREATE TRIGGER image_tags_fts_delete AFTER DELETE ON image_tags BEGIN
+            DELETE FROM image_fts WHERE rowid = OLD.image_id;
+            INSERT INTO image_fts (rowid, tags, text)
+                SELECT image_id, tags, text FROM image_fts_source WHERE image_id = OLD.image_id;
+        END;
+        CREATE TRIGGER image_tags_fts_update AFTER UPDATE ON image_tags BEGIN
+            DELETE FROM image_fts WHERE rowid IN (OLD.image_id, NEW.image_id);
+            INSERT INTO image_fts (rowid, tags, text)
+                SELECT image_id, tags, text FROM image_fts_source
+                WHERE image_id IN (OLD.image_id, NEW.image_id);
+        END;
+        CREATE TRIGGER images_fts_text AFTER UPDATE OF ocr_text ON images BEGIN
+            DELETE FROM image_fts WHERE rowid = NEW.id;
+            INSERT INTO image_fts (rowid, tags, text)
+                SELECT image_id, tags, text FROM image_fts_source WHERE image_id = NEW.id;
+        END;
+        CREATE TRIGGER images_fts_delete AFTER DELETE ON images BEGIN
+            DELETE FROM image_fts WHERE rowid = OLD.id;
+        END;
+        CREATE TRIGGER tags_fts_rename AFTER UPDATE OF name ON tags BEGIN
+            DELETE FROM image_fts WHERE rowid IN (SELECT image_id FROM image_tags WHERE tag_id = NEW.id);
+            INSERT INTO image_fts (rowid, tags, text)
+                SELECT image_id, tags, text FROM image_fts_source
+                WHERE image_id IN (SELECT image_id FROM image_tags WHERE tag_id = NEW.id);
+        END;",
+    )
+}
+
+fn migrate_v5_file_stats(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE images ADD COLUMN size INTEGER;
+        ALTER TABLE images ADD COLUMN mtime INTEGER;
+        ALTER TABLE images ADD COLUMN hash TEXT;
+        ALTER TABLE images ADD COLUMN indexed_at INTEGER;
+        CREATE INDEX idx_images_hash ON images(hash);",
+    )
+}
+
+fn migrate_v6_offline(tx: &Transaction) -> Result<()> {
+    tx.execute_batch("ALTER TABLE images ADD COLUMN offline INTEGER NOT NULL DEFAULT 0;")
+}
+
+fn migrate_v7_library_roots(tx: &Transaction) -> Result<()> {
+    // Folders tagged so far become roots, so existing libraries carry over
+    tx.execute_batch(
+        "CREATE TABLE roots (
+            id INTEGER PRIMARY KEY,
+            path TEXT NOT NULL UNIQUE,
+            include TEXT NOT NULL DEFAULT '',
+            exclude TEXT NOT NULL DEFAULT '',
+            recursive INTEGER NOT NULL DEFAULT 1,
+            last_scan INTEGER
+        );
+        ALTER TABLE images ADD COLUMN root_id INTEGER REFERENCES roots(id) ON DELETE SET NULL;
+        CREATE INDEX idx_images_root ON images(root_id);
+        INSERT OR IGNORE INTO roots (path, last_scan)
+            SELECT rtrim(folder, '/'), max(started_at) FROM tagging_runs
+            WHERE folder != '' GROUP BY rtrim(folder, '/');
+        UPDATE images SET root_id = (
+            SELECT r.id FROM roots r
+            WHERE substr(images.path, 1, length(r.path) + 1) = r.path || '/'
+            ORDER BY length(r.path) DESC LIMIT 1
+        );",
+    )
+}
+
+fn migrate_v8_embeddings(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE embeddings (
+            image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+            model TEXT NOT NULL,
+            vector BLOB NOT NULL,
+            PRIMARY KEY (image_id, model)
+        );",
+    )
+}
+
+fn migrate_v9_perceptual_hashes(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE images ADD COLUMN width INTEGER;
+        ALTER TABLE images ADD COLUMN height INTEGER;
+        ALTER TABLE images ADD COLUMN dhash INTEGER;
+        ALTER TABLE images ADD COLUMN phash INTEGER;",
+    )
+}
+
+fn migrate_v10_file_ops(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE file_ops (
+            id INTEGER PRIMARY KEY,
+            batch INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            original_path TEXT NOT NULL,
+            moved_to TEXT NOT NULL,
+            trash_info TEXT,
+            done_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            undone_at INTEGER
+        );
+        CREATE INDEX idx_file_ops_batch ON file_ops(batch);",
+    )
+}
+
+fn migrate_v11_faces(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE people (
+            id INTEGER PRIMARY KEY,
+            name TEXT UNIQUE COLLATE NOCASE
+        );
+        CREATE TABLE faces (
+            id INTEGER PRIMARY KEY,
+            image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+            x REAL NOT NULL,
+            y REAL NOT NULL,
+            w REAL NOT NULL,
+            h REAL NOT NULL,
+            score REAL NOT NULL,
+            embedding BLOB NOT NULL,
+            person_id INTEGER REFERENCES people(id) ON DELETE SET NULL
+        );
+        CREATE INDEX idx_faces_image ON faces(image_id);
+        CREATE INDEX idx_faces_person ON faces(person_id);",
+    )
+}
+
+fn migrate_v12_detections(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE detections (
+            id INTEGER PRIMARY KEY,
+            image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+            model TEXT NOT NULL,
+            label TEXT NOT NULL COLLATE NOCASE,
+            score REAL NOT NULL,
+            x REAL NOT NULL,
+            y REAL NOT NULL,
+            w REAL NOT NULL,
+            h REAL NOT NULL
+        );
+        CREATE INDEX idx_detections_image ON detections(image_id);
+        CREATE INDEX idx_detections_label ON detections(label);",
+    )
+}
+
+fn migrate_v13_palettes(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE palettes (
+            image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            rgb INTEGER NOT NULL,
+            lab_l REAL NOT NULL,
+            lab_a REAL NOT NULL,
+            lab_b REAL NOT NULL,
+            share REAL NOT NULL,
+            PRIMARY KEY (image_id, position)
+        );",
+    )
+}
+
+fn migrate_v14_tag_models(tx: &Transaction) -> Result<()> {
+    // Taggers sharing a source (two classifiers, say) keep separate rows, so
+    // the model joins the key. '' stands for no model, as NULLs never conflict.
+    // The legacy rename leaves the FTS view and triggers that name
+    // `image_tags` alone; only the triggers on the table itself go with it.
+    tx.execute_batch(
+        "CREATE TABLE image_tags_v14 (
+            image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+            tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            source TEXT NOT NULL DEFAULT 'imported',
+            confidence REAL NOT NULL DEFAULT 1.0,
+            model TEXT NOT NULL DEFAULT '',
+            added_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            run_id INTEGER REFERENCES tagging_runs(id) ON DELETE SET NULL,
+            PRIMARY KEY (image_id, tag_id, source, model)
+        );
+        INSERT INTO image_tags_v14 (image_id, tag_id, source, confidence, model, added_at, run_id)
+            SELECT image_id, tag_id, source, confidence, COALESCE(model, ''), added_at, run_id FROM image_tags;
+        DROP TABLE image_tags;
+        PRAGMA legacy_alter_table = ON;
+        ALTER TABLE image_tags_v14 RENAME TO image_tags;
+        PRAGMA legacy_alter_table = OFF;
+        CREATE INDEX idx_image_tags_tag ON image_tags(tag_id);
+        CREATE INDEX idx_image_tags_confidence ON image_tags(confidence);
+
+        CREATE TRIGGER image_tags_fts_insert AFTER INSERT ON image_tags BEGIN
+            DELETE FROM image_fts WHERE rowid = NEW.image_id;
+            INSERT INTO image_fts (rowid, tags, text)
+                SELECT image_id, tags, text FROM image_fts_source WHERE image_id = NEW.image_id;
+        END;
+        CREATE TRIGGER image_tags_fts_delete AFTER DELETE ON image_tags BEGIN
+            DELETE FROM image_fts WHERE rowid = OLD.image_id;
+            INSERT INTO image_fts (rowid, tags, text)
+                SELECT image_id, tags, text FROM image_fts_source WHERE image_id = OLD.image_id;
+        END;
+        CREATE TRIGGER image_tags_fts_update AFTER UPDATE ON image_tags BEGIN
+            DELETE FROM image_fts WHERE rowid IN (OLD.image_id, NEW.image_id);
+            INSERT INTO image_fts (rowid, tags, text)
+                SELECT image_id, tags, text FROM image_fts_source
+                WHERE image_id IN (OLD.image_id, NEW.image_id);
+        END;",
+    )
+}
+
+fn migrate_v15_moved_away(tx: &Transaction) -> Result<()> {
+    // Files a duplicate resolution moved away keep their rows, hidden, so
+    // undoing it brings back their tags, faces and edits
+    tx.execute_batch("ALTER TABLE images ADD COLUMN moved_away INTEGER NOT NULL DEFAULT 0;")
+}
+
+fn migrate_v16_tag_counts(tx: &Transaction) -> Result<()> {
+    // Instances per label from the object detector, NULL for other sources
+    tx.execute_batch("ALTER TABLE image_tags ADD COLUMN count INTEGER;")
+}
+
+fn upsert_image(conn: &Connection, path: &str) -> Result<i64> {
+    conn.execute("INSERT INTO images (path) VALUES (?1) ON CONFLICT(path) DO NOTHING", [path])?;
+    conn.query_row("SELECT id FROM images WHERE path = ?1", [path], |row| row.get(0))
+}
+
+fn upsert_tag(conn: &Connection, name: &str) -> Result<i64> {
+    conn.execute("INSERT INTO tags (name) VALUES (?1) ON CONFLICT(name) DO NOTHING", [name])?;
+    conn.query_row("SELECT id FROM tags WHERE name = ?1", [name], |row| row.get(0))
+}
+
+/// What the index knows about a file. Stats are missing for images indexed
+/// before they were recorded.
+pub struct IndexedFile {
+    pub stat: Option<FileStat>,
+    pub hash: Option<String>,
+}
+
+pub fn get_indexed_files(conn: &Connection) -> Result<HashMap<PathBuf, IndexedFile>> {
+    let mut stmt = conn.prepare("SELECT path, size, mtime, hash FROM images WHERE moved_away = 0")?;
+    let rows = stmt.query_map([], |row| {
+        let size: Option<u64> = row.get(1)?;
+        let mtime: Option<i64> = row.get(2)?;
+        let stat = size.zip(mtime).map(|(size, mtime)| FileStat { size, mtime });
+        Ok((PathBuf::from(row.get::<_, String>(0)?), IndexedFile { stat, hash: row.get(3)? }))
+    })?;
+    rows.collect()
+}
+
+/// Content hash the file was last indexed at.
+pub fn get_file_hash(conn: &Connection, path: &Path) -> Result<Option<String>> {
+    conn.query_row("SELECT hash FROM images WHERE path = ?1", [path.to_string_lossy()], |row| row.get(0))
+        .optional()
+        .map(Option::flatten)
+}
+
+/// Records the file state an image was indexed at, so later scans can skip it.
+/// A file back at a path it was moved away from takes its old row back.
+pub fn mark_indexed(conn: &Connection, path: &Path, stat: FileStat, hash: &str) -> Result<()> {
+    let path = path.to_string_lossy();
+    upsert_image(conn, &path)?;
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS images (path TEXT PRIMARY KEY, tags TEXT)",
-        [],
+        "UPDATE images SET size = ?1, mtime = ?2, hash = ?3, indexed_at = strftime('%s', 'now'), moved_away = 0
+         WHERE path = ?4",
+        params![stat.size, stat.mtime, hash, path],
     )?;
-    Ok(conn)
+    Ok(())
+}
+
+/// Upright size and perceptual hashes of an image, for duplicate detection.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageHashes {
+    pub width: u32,
+    pub height: u32,
+    pub dhash: u64,
+    pub phash: u64,
 }
 
-pub fn store_tags(conn: &Connection, path: &std::path::Path, tags: &[String]) -> Result<()> {
-    let tags_str = tags.join(",");
+pub fn store_image_hashes(conn: &Connection, path: &Path, hashes: ImageHashes) -> Result<()> {
+    let path = path.to_string_lossy();
+    upsert_image(conn, &path)?;
+    // SQLite integers are signed; the bits are stored as they are
     conn.execute(
-        "INSERT OR REPLACE INTO images (path, tags) VALUES (?, ?)",
-        [path.to_str().unwrap(), &tags_str],
+        "UPDATE images SET width = ?1, height = ?2, dhash = ?3, phash = ?4 WHERE path = ?5",
+        params![hashes.width, hashes.height, hashes.dhash as i64, hashes.phash as i64, path],
     )?;
     Ok(())
 }
 
-pub fn search_images(conn: &Connection, query: &str) -> Result<Vec<PathBuf>> {
-    let mut stmt = conn.prepare("SELECT path FROM images WHERE tags LIKE ?")?;
-    let like_query = format!("%{}%", query);
-    let rows = stmt.query_map([&like_query], |row| row.get::<_, String>(0))?;
+/// An indexed image with its perceptual hashes, as the Duplicates view lists it.
+#[derive(Clone, Debug)]
+pub struct HashedImage {
+    pub path: PathBuf,
+    /// Content hash; equal for byte-identical files.
+    pub hash: Option<String>,
+    pub size: Option<u64>,
+    pub mtime: Option<i64>,
+    pub hashes: ImageHashes,
+    pub offline: bool,
+}
+
+/// Every image with perceptual hashes, limited to `root` when one is given.
+pub fn get_hashed_images(conn: &Connection, root: Option<i64>) -> Result<Vec<HashedImage>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, hash, size, mtime, width, height, dhash, phash, offline FROM images
+         WHERE phash IS NOT NULL AND moved_away = 0 AND (?1 IS NULL OR root_id = ?1) ORDER BY path",
+    )?;
+    let rows = stmt.query_map([root], |row| {
+        Ok(HashedImage {
+            path: PathBuf::from(row.get::<_, String>(0)?),
+            hash: row.get(1)?,
+            size: row.get(2)?,
+            mtime: row.get(3)?,
+            hashes: ImageHashes {
+                width: row.get(4)?,
+                height: row.get(5)?,
+                dhash: row.get::<_, i64>(6)? as u64,
+                phash: row.get::<_, i64>(7)? as u64,
+            },
+            offline: row.get(8)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Online images indexed before perceptual hashes were recorded.
+pub fn get_unhashed_images(conn: &Connection, root: Option<i64>) -> Result<Vec<PathBuf>> {
+    let mut stmt = conn.prepare(
+        "SELECT path FROM images WHERE phash IS NULL AND offline = 0 AND moved_away = 0
+         AND (?1 IS NULL OR root_id = ?1) ORDER BY path",
+    )?;
+    let rows = stmt.query_map([root], |row| row.get::<_, String>(0))?;
     rows.map(|r| r.map(PathBuf::from)).collect()
 }
 
-pub fn get_images_with_tag(conn: &Connection, tag: &str) -> Result<Vec<(PathBuf, Vec<String>)>> {
-    let mut stmt = conn.prepare("SELECT path, tags FROM images WHERE tags LIKE ?")?;
-    let like_query = format!("%{}%", tag);
-    let rows = stmt.query_map([&like_query], |row| {
-        let path: String = row.get(0)?;
-        let tags_str: String = row.get(1)?;
-        let tags: Vec<String> = tags_str.split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect();
-        Ok((PathBuf::from(path), tags))
+/// A file moved away by a duplicate resolution, as the undo log has it.
+pub struct FileOp {
+    pub id: i64,
+    /// `trash` or `quarantine`.
+    pub action: String,
+    pub original: PathBuf,
+    pub moved_to: PathBuf,
+    pub trash_info: Option<PathBuf>,
+}
+
+/// Id for the moves of one resolution, which are undone together.
+pub fn next_file_op_batch(conn: &Connection) -> Result<i64> {
+    conn.query_row("SELECT COALESCE(MAX(batch), 0) + 1 FROM file_ops", [], |row| row.get(0))
+}
+
+pub fn record_file_op(
+    conn: &Connection,
+    batch: i64,
+    action: &str,
+    original: &Path,
+    moved_to: &Path,
+    trash_info: Option<&Path>,
+) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO file_ops (batch, action, original_path, moved_to, trash_info) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            batch,
+            action,
+            original.to_string_lossy(),
+            moved_to.to_string_lossy(),
+            trash_info.map(|p| p.to_string_lossy().into_owned()),
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Drops a logged move that did not happen.
+pub fn delete_file_op(conn: &Connection, id: i64) -> Result<()> {
+    conn.execute("DELETE FROM file_ops WHERE id = ?1", [id])?;
+    Ok(())
+}
+
+/// Moves of the latest batch that are not undone yet.
+pub fn get_last_file_ops(conn: &Connection) -> Result<Vec<FileOp>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, action, original_path, moved_to, trash_info FROM file_ops
+         WHERE undone_at IS NULL AND batch = (SELECT MAX(batch) FROM file_ops WHERE undone_at IS NULL)
+         ORDER BY id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(FileOp {
+            id: row.get(0)?,
+            action: row.get(1)?,
+            original: PathBuf::from(row.get::<_, String>(2)?),
+            moved_to: PathBuf::from(row.get::<_, String>(3)?),
+            trash_info: row.get::<_, Option<String>>(4)?.map(PathBuf::from),
+        })
+    })?;
+    rows.collect()
+}
+
+pub fn mark_file_op_undone(conn: &Connection, id: i64) -> Result<()> {
+    conn.execute("UPDATE file_ops SET undone_at = strftime('%s', 'now') WHERE id = ?1", [id])?;
+    Ok(())
+}
+
+/// Points an existing image row at the path its file was moved to, keeping
+/// its id and therefore all of its tags and history.
+pub fn move_image(conn: &Connection, old: &Path, new: &Path, stat: FileStat) -> Result<()> {
+    conn.execute(
+        "UPDATE images SET path = ?1, size = ?2, mtime = ?3 WHERE path = ?4",
+        params![new.to_string_lossy(), stat.size, stat.mtime, old.to_string_lossy()],
+    )?;
+    Ok(())
+}
+
+/// Hides an image whose file a duplicate resolution moved away, or shows it
+/// again when the move is undone. The row and everything attached to it stay.
+pub fn set_moved_away(conn: &Connection, path: &Path, moved_away: bool) -> Result<()> {
+    conn.execute(
+        "UPDATE images SET moved_away = ?1 WHERE path = ?2",
+        params![moved_away, path.to_string_lossy()],
+    )?;
+    Ok(())
+}
+
+/// Every indexed path with its offline flag.
+pub fn get_image_paths(conn: &Connection) -> Result<Vec<(PathBuf, bool)>> {
+    let mut stmt = conn.prepare("SELECT path, offline FROM images WHERE moved_away = 0 ORDER BY path")?;
+    let rows = stmt.query_map([], |row| Ok((PathBuf::from(row.get::<_, String>(0)?), row.get(1)?)))?;
+    rows.collect()
+}
+
+/// Deletes index entries; their tags and search rows go with them.
+pub fn remove_images(conn: &Connection, paths: &[PathBuf]) -> Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut removed = 0;
+    for path in paths {
+        removed += tx.execute("DELETE FROM images WHERE path = ?1", [path.to_string_lossy()])?;
+    }
+    tx.commit()?;
+    Ok(removed)
+}
+
+pub fn set_offline(conn: &Connection, paths: &[PathBuf], offline: bool) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for path in paths {
+        tx.execute(
+            "UPDATE images SET offline = ?1 WHERE path = ?2",
+            params![offline, path.to_string_lossy()],
+        )?;
+    }
+    tx.commit()
+}
+
+#[derive(Clone, Debug)]
+pub struct LibraryRoot {
+    pub id: i64,
+    pub path: PathBuf,
+    /// Comma-separated glob patterns; empty means every image.
+    pub include: String,
+    pub exclude: String,
+    pub recursive: bool,
+    /// Unix seconds of the last completed scan.
+    pub last_scan: Option<i64>,
+}
+
+impl LibraryRoot {
+    pub fn rules(&self) -> ScanRules {
+        ScanRules::new(&self.include, &self.exclude, self.recursive)
+    }
+}
+
+pub fn get_roots(conn: &Connection) -> Result<Vec<LibraryRoot>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, path, include, exclude, recursive, last_scan FROM roots ORDER BY path",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(LibraryRoot {
+            id: row.get(0)?,
+            path: PathBuf::from(row.get::<_, String>(1)?),
+            include: row.get(2)?,
+            exclude: row.get(3)?,
+            recursive: row.get(4)?,
+            last_scan: row.get(5)?,
+        })
+    })?;
+    rows.collect()
+}
+
+pub fn add_root(conn: &Connection, path: &Path) -> Result<()> {
+    let path = path.to_string_lossy();
+    let path = if path.len() > 1 { path.trim_end_matches('/') } else { &path };
+    conn.execute("INSERT OR IGNORE INTO roots (path) VALUES (?1)", [path])?;
+    Ok(())
+}
+
+pub fn update_root(conn: &Connection, root: &LibraryRoot) -> Result<()> {
+    conn.execute(
+        "UPDATE roots SET include = ?1, exclude = ?2, recursive = ?3 WHERE id = ?4",
+        params![root.include, root.exclude, root.recursive, root.id],
+    )?;
+    Ok(())
+}
+
+/// Forgets a root and everything indexed under it. Files are not touched.
+pub fn remove_root(conn: &Connection, root_id: i64) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM images WHERE root_id = ?1", [root_id])?;
+    tx.execute("DELETE FROM roots WHERE id = ?1", [root_id])?;
+    tx.commit()
+}
+
+/// Links every image below the root to it, unless a nested root holds the
+/// image (the longest matching root wins, as in `migrate_v7`). Cancelled
+/// scans run this too, so what they indexed is found by root.
+pub fn assign_root(conn: &Connection, root: &LibraryRoot) -> Result<()> {
+    conn.execute(
+        "UPDATE images SET root_id = (
+            SELECT r.id FROM roots r
+            WHERE substr(images.path, 1, length(r.path) + 1) = r.path || '/'
+            ORDER BY length(r.path) DESC LIMIT 1
+         )
+         WHERE substr(path, 1, length(?1) + 1) = ?1 || '/'",
+        [root.path.to_string_lossy()],
+    )?;
+    Ok(())
+}
+
+/// Assigns the root's images and stamps the scan time.
+pub fn finish_root_scan(conn: &Connection, root: &LibraryRoot) -> Result<()> {
+    assign_root(conn, root)?;
+    conn.execute(
+        "UPDATE roots SET last_scan = strftime('%s', 'now') WHERE id = ?1",
+        [root.id],
+    )?;
+    Ok(())
+}
+
+/// Records the settings a tagging run uses and returns its id.
+pub fn start_run(conn: &Connection, folder: &str, config: &TaggingConfig) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO tagging_runs (folder, min_probability, max_tags, cumulative_cutoff)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![folder, config.min_probability, config.max_tags as i64, config.cumulative_cutoff],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn finish_run(conn: &Connection, run_id: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE tagging_runs SET finished_at = strftime('%s', 'now') WHERE id = ?1",
+        [run_id],
+    )?;
+    Ok(())
+}
+
+/// Replaces the tags `source` and `model` produced for `path`, leaving tags
+/// from other sources and models untouched so a single tagger can be re-run
+/// on its own.
+pub fn store_tags(
+    conn: &Connection,
+    path: &Path,
+    source: TagSource,
+    model: Option<&str>,
+    tags: &[ScoredTag],
+    run_id: Option<i64>,
+) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let image_id = upsert_image(&tx, &path.to_string_lossy())?;
+    // Rows are keyed by source and model, '' meaning none
+    let model = model.unwrap_or("");
+    tx.execute(
+        "DELETE FROM image_tags WHERE image_id = ?1 AND source = ?2 AND model = ?3",
+        params![image_id, source.as_str(), model],
+    )?;
+    for tag in tags {
+        let tag_id = upsert_tag(&tx, &tag.name)?;
+        tx.execute(
+            "INSERT INTO image_tags (image_id, tag_id, source, confidence, model, run_id, count)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(image_id, tag_id, source, model) DO UPDATE SET
+                 confidence = excluded.confidence,
+                 run_id = excluded.run_id,
+                 count = excluded.count",
+            params![image_id, tag_id, source.as_str(), tag.confidence, model, run_id, tag.count],
+        )?;
+    }
+    tx.commit()
+}
+
+pub fn get_tags(conn: &Connection, path: &Path) -> Result<Vec<ScoredTag>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.name, it.confidence, it.source, NULLIF(it.model, ''), it.count FROM images i
+         JOIN image_tags it ON it.image_id = i.id
+         JOIN tags t ON t.id = it.tag_id
+         WHERE i.path = ?1
+         ORDER BY it.confidence DESC, t.name",
+    )?;
+    let rows = stmt.query_map([path.to_string_lossy()], |row| {
+        Ok(ScoredTag {
+            name: row.get(0)?,
+            confidence: row.get(1)?,
+            source: TagSource::parse(&row.get::<_, String>(2)?),
+            model: row.get(3)?,
+            count: row.get(4)?,
+        })
     })?;
     rows.collect()
 }
+
+/// Stores the full recognized text for `path`, which feeds phrase search.
+pub fn store_ocr_text(conn: &Connection, path: &Path, text: &str) -> Result<()> {
+    let path = path.to_string_lossy();
+    upsert_image(conn, &path)?;
+    conn.execute("UPDATE images SET ocr_text = ?1 WHERE path = ?2", params![text, path])?;
+    Ok(())
+}
+
+/// Stores `model`'s embedding of `path`, replacing the previous one.
+pub fn store_embedding(conn: &Connection, path: &Path, model: &str, vector: &[f32]) -> Result<()> {
+    let image_id = upsert_image(conn, &path.to_string_lossy())?;
+    let bytes: Vec<u8> = vector.iter().flat_map(|x| x.to_le_bytes()).collect();
+    conn.execute(
+        "INSERT OR REPLACE INTO embeddings (image_id, model, vector) VALUES (?1, ?2, ?3)",
+        params![image_id, model, bytes],
+    )?;
+    Ok(())
+}
+
+/// Cosine similarity of every image `semantic.model` embedded to the search
+/// vector, by image id, read in one pass.
+fn get_similarities(conn: &Connection, semantic: &Semantic) -> Result<HashMap<i64, f32>> {
+    let mut stmt = conn.prepare("SELECT image_id, vector FROM embeddings WHERE model = ?1")?;
+    let rows = stmt.query_map([semantic.model], |row| {
+        let vector = decode_vector(&row.get::<_, Vec<u8>>(1)?);
+        Ok((row.get(0)?, vector.iter().zip(semantic.vector).map(|(a, b)| a * b).sum()))
+    })?;
+    rows.collect()
+}
+
+fn decode_vector(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+}
+
+/// Replaces the faces stored for `path`. The new faces have no person until
+/// `faces::cluster_faces` runs.
+pub fn store_faces(conn: &Connection, path: &Path, faces: &[Face]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let image_id = upsert_image(&tx, &path.to_string_lossy())?;
+    tx.execute("DELETE FROM faces WHERE image_id = ?1", [image_id])?;
+    for face in faces {
+        let bytes: Vec<u8> = face.embedding.iter().flat_map(|x| x.to_le_bytes()).collect();
+        tx.execute(
+            "INSERT INTO faces (image_id, x, y, w, h, score, embedding) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![image_id, face.bbox.x, face.bbox.y, face.bbox.w, face.bbox.h, face.score, bytes],
+        )?;
+    }
+    tx.commit()
+}
+
+/// Replaces the objects `model` found in `path`.
+pub fn store_detections(conn: &Connection, path: &Path, model: &str, detections: &[Detection]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let image_id = upsert_image(&tx, &path.to_string_lossy())?;
+    tx.execute("DELETE FROM detections WHERE image_id = ?1 AND model = ?2", params![image_id, model])?;
+    for d in detections {
+        tx.execute(
+            "INSERT INTO detections (image_id, model, label, score, x, y, w, h) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![image_id, model, d.label, d.score, d.bbox.x, d.bbox.y, d.bbox.w, d.bbox.h],
+        )?;
+    }
+    tx.commit()
+}
+
+/// Every object found in `path`, most confident first.
+pub fn get_detections(conn: &Connection, path: &Path) -> Result<Vec<Detection>> {
+    let mut stmt = conn.prepare(
+        "SELECT d.label, d.score, d.x, d.y, d.w, d.h FROM detections d JOIN images i ON i.id = d.image_id
+         WHERE i.path = ?1 ORDER BY d.score DESC",
+    )?;
+    let rows = stmt.query_map([path.to_string_lossy()], |row| {
+        Ok(Detection {
+            label: row.get(0)?,
+            score: row.get(1)?,
+            bbox: BoundingBox { x: row.get(2)?, y: row.get(3)?, w: row.get(4)?, h: row.get(5)? },
+        })
+    })?;
+    rows.collect()
+}
+
+/// Replaces the palette of `path`. Colors are stored as `0xRRGGBB`.
+pub fn store_palette(conn: &Connection, path: &Path, palette: &[PaletteColor]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let image_id = upsert_image(&tx, &path.to_string_lossy())?;
+    tx.execute("DELETE FROM palettes WHERE image_id = ?1", [image_id])?;
+    for (position, color) in palette.iter().enumerate() {
+        let [r, g, b] = color.rgb.map(u32::from);
+        tx.execute(
+            "INSERT INTO palettes (image_id, position, rgb, lab_l, lab_a, lab_b, share) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![image_id, position, (r << 16) | (g << 8) | b, color.lab[0], color.lab[1], color.lab[2], color.share],
+        )?;
+    }
+    tx.commit()
+}
+
+/// The palette of `path`, largest share first.
+pub fn get_palette(conn: &Connection, path: &Path) -> Result<Vec<PaletteColor>> {
+    let mut stmt = conn.prepare(
+        "SELECT p.rgb, p.lab_l, p.lab_a, p.lab_b, p.share FROM palettes p JOIN images i ON i.id = p.image_id
+         WHERE i.path = ?1 ORDER BY p.position",
+    )?;
+    let rows = stmt.query_map([path.to_string_lossy()], |row| {
+        let rgb: u32 = row.get(0)?;
+        Ok(PaletteColor {
+            rgb: [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8],
+            lab: [row.get(1)?, row.get(2)?, row.get(3)?],
+            share: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// A stored face, as clustering sees it.
+pub struct StoredFace {
+    pub id: i64,
+    pub path: PathBuf,
+    pub person_id: Option<i64>,
+    pub embedding: Vec<f32>,
+}
+
+pub fn get_faces(conn: &Connection) -> Result<Vec<StoredFace>> {
+    let mut stmt = conn.prepare(
+        "SELECT f.id, i.path, f.person_id, f.embedding FROM faces f JOIN images i ON i.id = f.image_id ORDER BY f.id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(StoredFace {
+            id: row.get(0)?,
+            path: PathBuf::from(row.get::<_, String>(1)?),
+            person_id: row.get(2)?,
+            embedding: decode_vector(&row.get::<_, Vec<u8>>(3)?),
+        })
+    })?;
+    rows.collect()
+}
+
+/// Where a person's face is, for showing it.
+pub struct FaceSample {
+    pub path: PathBuf,
+    pub hash: Option<String>,
+    pub bbox: BoundingBox,
+}
+
+/// A cluster of faces, named or not.
+pub struct Person {
+    pub id: i64,
+    pub name: Option<String>,
+    pub faces: usize,
+    /// The most confident few.
+    pub samples: Vec<FaceSample>,
+}
+
+/// Faces shown per person in the People window.
+const PERSON_SAMPLES: usize = 6;
+
+/// Named people first, then the rest by how often they appear.
+pub fn get_people(conn: &Connection) -> Result<Vec<Person>> {
+    let mut stmt = conn.prepare(
+        "SELECT p.id, p.name, COUNT(f.id) AS n FROM people p LEFT JOIN faces f ON f.person_id = p.id
+         GROUP BY p.id ORDER BY p.name IS NULL, p.name, n DESC",
+    )?;
+    let people = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, usize>(2)?)))?
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut samples = conn.prepare(
+        "SELECT i.path, i.hash, f.x, f.y, f.w, f.h FROM faces f JOIN images i ON i.id = f.image_id
+         WHERE f.person_id = ?1 AND i.moved_away = 0 ORDER BY f.score DESC LIMIT ?2",
+    )?;
+    people.into_iter().map(|(id, name, faces)| {
+        let samples = samples
+            .query_map(params![id, PERSON_SAMPLES], |row| {
+                Ok(FaceSample {
+                    path: PathBuf::from(row.get::<_, String>(0)?),
+                    hash: row.get(1)?,
+                    bbox: BoundingBox { x: row.get(2)?, y: row.get(3)?, w: row.get(4)?, h: row.get(5)? },
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Person { id, name, faces, samples })
+    }).collect()
+}
+
+pub fn create_person(conn: &Connection) -> Result<i64> {
+    conn.execute("INSERT INTO people (name) VALUES (NULL)", [])?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn set_face_person(conn: &Connection, face_id: i64, person_id: i64) -> Result<()> {
+    conn.execute("UPDATE faces SET person_id = ?1 WHERE id = ?2", params![person_id, face_id])?;
+    Ok(())
+}
+
+/// Drops unnamed people left without faces. Named people stay, so a name
+/// survives its images being retagged.
+pub fn remove_empty_people(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "DELETE FROM people WHERE name IS NULL
+         AND id NOT IN (SELECT person_id FROM faces WHERE person_id IS NOT NULL)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Names a person. If someone already has the name, the faces move to them,
+/// which merges two clusters of the same person. Returns the id that holds
+/// the name afterwards.
+pub fn name_person(conn: &Connection, person_id: i64, name: &str) -> Result<i64> {
+    let tx = conn.unchecked_transaction()?;
+    let existing: Option<i64> = tx
+        .query_row("SELECT id FROM people WHERE name = ?1 AND id != ?2", params![name, person_id], |row| row.get(0))
+        .optional()?;
+    let id = match existing {
+        Some(existing) => {
+            tx.execute("UPDATE faces SET person_id = ?1 WHERE person_id = ?2", params![existing, person_id])?;
+            tx.execute("DELETE FROM people WHERE id = ?1", [person_id])?;
+            existing
+        }
+        None => {
+            tx.execute("UPDATE people SET name = ?1 WHERE id = ?2", params![name, person_id])?;
+            person_id
+        }
+    };
+    tx.commit()?;
+    Ok(id)
+}
+
+pub fn get_person_images(conn: &Connection, person_id: i64) -> Result<Vec<PathBuf>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT i.path FROM faces f JOIN images i ON i.id = f.image_id
+         WHERE f.person_id = ?1 AND i.moved_away = 0 ORDER BY i.path",
+    )?;
+    let rows = stmt.query_map([person_id], |row| row.get::<_, String>(0))?;
+    rows.map(|r| r.map(PathBuf::from)).collect()
+}
+
+/// Names of the people whose faces are in `path`.
+pub fn get_person_names(conn: &Connection, path: &Path) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT p.name FROM faces f
+         JOIN images i ON i.id = f.image_id
+         JOIN people p ON p.id = f.person_id
+         WHERE i.path = ?1 AND p.name IS NOT NULL ORDER BY p.name",
+    )?;
+    let rows = stmt.query_map([path.to_string_lossy()], |row| row.get::<_, String>(0))?;
+    rows.collect()
+}
+
+/// Distinct tag names for `path`, as written into file metadata.
+pub fn get_tag_names(conn: &Connection, path: &Path) -> Result<Vec<String>> {
+    let mut names: Vec<String> = get_tags(conn, path)?.into_iter().map(|t| t.name).collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+pub struct SearchHit {
+    pub path: PathBuf,
+    /// Highest confidence among the tags that matched the query, or 0.0 when
+    /// only the recognized text matched.
+    pub confidence: f32,
+    /// BM25 score, lower is better.
+    pub rank: f64,
+    /// The file lives on a drive that is not mounted right now.
+    pub offline: bool,
+    /// Content hash, which keys the cached thumbnail.
+    pub hash: Option<String>,
+    /// Cosine similarity to the `about:` text or the "More like this" image.
+    pub similarity: Option<f32>,
+    pub tags: Vec<ScoredTag>,
+}
+
+/// Text embedding for a semantic search, and the model that produced it.
+pub struct Semantic<'a> {
+    pub model: &'a str,
+    pub vector: &'a [f32],
+}
+
+/// Semantic searches return the closest images only; everything scores
+/// above zero against everything else.
+const SEMANTIC_LIMIT: usize = 200;
+
+fn tag_matches(tag: &str, terms: &[Text]) -> bool {
+    let tag = tag.to_lowercase();
+    terms.iter().any(|term| {
+        let value = term.value.to_lowercase();
+        if term.prefix {
+            tag.starts_with(&value) || tag.split_whitespace().any(|word| word.starts_with(&value))
+        } else {
+            tag == value || tag.split_whitespace().any(|word| word == value)
+        }
+    })
+}
+
+/// Runs `query`, limited to images under `root` when one is given. With
+/// `semantic`, hits are ordered by similarity to its vector instead.
+pub fn search_images(
+    conn: &Connection,
+    query: &Expr,
+    min_confidence: f32,
+    root: Option<i64>,
+    semantic: Option<&Semantic>,
+) -> Result<Vec<SearchHit>> {
+    let mut compiled = query.compile();
+    if let Some(root_id) = root {
+        compiled.sql = format!("({}) AND i.root_id = ?", compiled.sql);
+        compiled.params.push(Value::Integer(root_id));
+    }
+    let mut params = Vec::with_capacity(compiled.params.len() + 1);
+    // Images that only match through filters (path:, ext:, ...) rank last
+    let rank_sql = match query.rank_match() {
+        Some(rank_match) => {
+            params.push(Value::Text(rank_match));
+            "COALESCE((SELECT bm25(image_fts, 2.0, 1.0) FROM image_fts
+                       WHERE image_fts MATCH ? AND image_fts.rowid = i.id), 0.0)"
+        }
+        None => "0.0",
+    };
+    params.extend(compiled.params);
+
+    let sql = format!(
+        "SELECT i.path, {} AS rank, i.offline, i.hash, i.id FROM images i
+         WHERE i.moved_away = 0 AND ({}) ORDER BY rank, i.path",
+        rank_sql, compiled.sql
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt
+        .query_map(params_from_iter(params), |row| {
+            Ok((
+                PathBuf::from(row.get::<_, String>(0)?),
+                row.get::<_, f64>(1)?,
+                row.get::<_, bool>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    // Semantic results keep the closest images that have an embedding, so
+    // tags are only read for those
+    let rows: Vec<_> = match semantic {
+        Some(semantic) => {
+            let scores = get_similarities(conn, semantic)?;
+            let mut scored: Vec<_> = rows.into_iter()
+                .filter_map(|(path, rank, offline, hash, image_id)| {
+                    scores.get(&image_id).map(|&similarity| (path, rank, offline, hash, Some(similarity)))
+                })
+                .collect();
+            scored.sort_by(|a, b| b.4.partial_cmp(&a.4).unwrap_or(std::cmp::Ordering::Equal));
+            scored
+        }
+        None => rows.into_iter().map(|(path, rank, offline, hash, _)| (path, rank, offline, hash, None)).collect(),
+    };
+
+    let terms = query.positive_terms();
+    let mut hits = Vec::with_capacity(rows.len().min(SEMANTIC_LIMIT));
+    for (path, rank, offline, hash, similarity) in rows {
+        if semantic.is_some() && hits.len() == SEMANTIC_LIMIT {
+            break;
+        }
+        let tags = get_tags(conn, &path)?;
+        let confidence = tags
+            .iter()
+            .filter(|t| tag_matches(&t.name, &terms))
+            .map(|t| t.confidence)
+            .fold(0.0, f32::max);
+        if min_confidence > 0.0 && confidence < min_confidence {
+            continue;
+        }
+        hits.push(SearchHit { path, confidence, rank, offline, hash, similarity, tags });
+    }
+    Ok(hits)
+}
+
+/// Images that look like `path`, most similar first, by the embedding of
+/// `preferred` or else any model that embedded `path`. Includes `path` itself.
+/// `None` when `path` has no embedding.
+pub fn similar_images(
+    conn: &Connection,
+    path: &Path,
+    preferred: &str,
+    min_similarity: f32,
+    root: Option<i64>,
+) -> Result<Option<Vec<SearchHit>>> {
+    let source: Option<(String, Vec<u8>)> = conn
+        .query_row(
+            "SELECT e.model, e.vector FROM embeddings e JOIN images i ON i.id = e.image_id
+             WHERE i.path = ?1 ORDER BY e.model = ?2 DESC, e.model LIMIT 1",
+            params![path.to_string_lossy(), preferred],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((model, vector)) = source else {
+        return Ok(None);
+    };
+    let vector = decode_vector(&vector);
+
+    let mut stmt = conn.prepare(
+        "SELECT i.path, i.offline, i.hash, e.vector FROM embeddings e JOIN images i ON i.id = e.image_id
+         WHERE e.model = ?1 AND i.moved_away = 0 AND (?2 IS NULL OR i.root_id = ?2)",
+    )?;
+    let mut scored = Vec::new();
+    let rows = stmt.query_map(params![model, root], |row| {
+        Ok((
+            PathBuf::from(row.get::<_, String>(0)?),
+            row.get::<_, bool>(1)?,
+            row.get::<_, Option<String>>(2)?,
+            row.get::<_, Vec<u8>>(3)?,
+        ))
+    })?;
+    for row in rows {
+        let (path, offline, hash, other) = row?;
+        let similarity: f32 = decode_vector(&other).iter().zip(&vector).map(|(a, b)| a * b).sum();
+        if similarity >= min_similarity {
+            scored.push((path, offline, hash, similarity));
+        }
+    }
+    scored.sort_by(|a, b| b.3.partial_cmp(&a.3).unwrap_or(std::cmp::Ordering::Equal));
+    scored.truncate(SEMANTIC_LIMIT);
+
+    let mut hits = Vec::with_capacity(scored.len());
+    for (path, offline, hash, similarity) in scored {
+        let tags = get_tags(conn, &path)?;
+        hits.push(SearchHit { path, confidence: 0.0, rank: 0.0, offline, hash, similarity: Some(similarity), tags });
+    }
+    Ok(Some(hits))
+}
+
+pub fn get_images_with_tag(conn: &Connection, tag: &str) -> Result<Vec<PathBuf>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT i.path FROM images i
+         JOIN image_tags it ON it.image_id = i.id
+         JOIN tags t ON t.id = it.tag_id
+         WHERE t.name = ?1 AND i.moved_away = 0
+         ORDER BY i.path",
+    )?;
+    let rows = stmt.query_map([tag], |row| row.get::<_, String>(0))?;
+    rows.map(|r| r.map(PathBuf::from)).collect()
+}
+
+/// Renames `old` to `new` everywhere, merging into `new` if it already exists.
+pub fn rename_tag(conn: &Connection, old: &str, new: &str) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let old_id: Option<i64> = tx
+        .query_row("SELECT id FROM tags WHERE name = ?1", [old], |row| row.get(0))
+        .optional()?;
+    let Some(old_id) = old_id else {
+        return Ok(());
+    };
+    let new_id = upsert_tag(&tx, new)?;
+    if new_id == old_id {
+        // Same tag under NOCASE, only the spelling changes.
+        tx.execute("UPDATE tags SET name = ?1 WHERE id = ?2", params![new, old_id])?;
+    } else {
+        tx.execute(
+            // Scalar max() is NULL if either side is, hence the coalesce
+            "INSERT INTO image_tags (image_id, tag_id, source, confidence, model, added_at, run_id, count)
+             SELECT image_id, ?2, source, confidence, model, added_at, run_id, count FROM image_tags WHERE tag_id = ?1
+             ON CONFLICT(image_id, tag_id, source, model) DO UPDATE SET
+                 confidence = max(confidence, excluded.confidence),
+                 count = coalesce(max(count, excluded.count), count, excluded.count)",
+            params![old_id, new_id],
+        )?;
+        tx.execute("DELETE FROM tags WHERE id = ?1", [old_id])?;
+    }
+    tx.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags_of(conn: &Connection, path: &str) -> Vec<(String, String, String, Option<u32>)> {
+        let mut stmt = conn.prepare(
+            "SELECT t.name, it.source, it.model, it.count FROM image_tags it
+             JOIN images i ON i.id = it.image_id JOIN tags t ON t.id = it.tag_id
+             WHERE i.path = ?1 ORDER BY t.name",
+        ).unwrap();
+        stmt.query_map([path], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    fn fts_hits(conn: &Connection, query: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(
+            "SELECT i.path FROM image_fts JOIN images i ON i.id = image_fts.rowid
+             WHERE image_fts MATCH ?1 ORDER BY i.path",
+        ).unwrap();
+        stmt.query_map([query], |row| row.get(0)).unwrap().collect::<Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn upgrades_legacy_catalog() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE images (path TEXT PRIMARY KEY, tags TEXT);
+            INSERT INTO images VALUES ('/photos/a.jpg', 'dog, Beach,,dog , sunset');
+            INSERT INTO images VALUES ('/photos/b.jpg', 'beach');
+            INSERT INTO images VALUES ('/photos/c.jpg', NULL);",
+        ).unwrap();
+        migrate(&mut conn).unwrap();
+
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        let legacy = conn.prepare("SELECT 1 FROM sqlite_master WHERE name = 'images_legacy'").unwrap().exists([]).unwrap();
+        assert!(!legacy);
+
+        // Tag names are unique without regard to case, first spelling wins
+        let names: Vec<String> = conn.prepare("SELECT name FROM tags ORDER BY id").unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .collect::<Result<_>>().unwrap();
+        assert_eq!(names, ["dog", "Beach", "sunset"]);
+        let imported = |name: &str| (name.to_string(), "imported".to_string(), String::new(), None);
+        assert_eq!(tags_of(&conn, "/photos/a.jpg"), vec![imported("Beach"), imported("dog"), imported("sunset")]);
+        assert_eq!(tags_of(&conn, "/photos/b.jpg"), vec![imported("Beach")]);
+        assert!(tags_of(&conn, "/photos/c.jpg").is_empty());
+
+        // The search index matches its source after image_tags was rebuilt
+        let images: usize = conn.query_row("SELECT count(*) FROM images", [], |row| row.get(0)).unwrap();
+        let in_sync: usize = conn.query_row(
+            "SELECT count(*) FROM image_fts f JOIN image_fts_source s ON s.image_id = f.rowid
+             WHERE f.tags = s.tags AND f.text = s.text",
+            [],
+            |row| row.get(0),
+        ).unwrap();
+        assert_eq!(in_sync, images);
+        assert_eq!(fts_hits(&conn, "beach"), ["/photos/a.jpg", "/photos/b.jpg"]);
+
+        // and the rebuilt table's triggers still keep it up to date
+        let cat = ScoredTag::new("cat", 0.9, TagSource::Classifier, Some("resnet"));
+        store_tags(&conn, Path::new("/photos/c.jpg"), TagSource::Classifier, Some("resnet"), &[cat], None).unwrap();
+        assert_eq!(fts_hits(&conn, "cat"), ["/photos/c.jpg"]);
+        rename_tag(&conn, "sunset", "dusk").unwrap();
+        assert_eq!(fts_hits(&conn, "dusk"), ["/photos/a.jpg"]);
+        assert!(fts_hits(&conn, "sunset").is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+/// Axis-aligned box relative to the upright image, each value in `0.0..=1.0`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl BoundingBox {
+    /// From top-left and bottom-right corners, clamped to the image.
+    pub fn from_corners(x1: f32, y1: f32, x2: f32, y2: f32) -> Self {
+        let (x1, y1) = (x1.clamp(0.0, 1.0), y1.clamp(0.0, 1.0));
+        let (x2, y2) = (x2.clamp(x1, 1.0), y2.clamp(y1, 1.0));
+        Self { x: x1, y: y1, w: x2 - x1, h: y2 - y1 }
+    }
+
+    pub fn area(&self) -> f32 {
+        self.w * self.h
+    }
+
+    /// Intersection over union.
+    pub fn iou(&self, other: &BoundingBox) -> f32 {
+        let w = (self.x + self.w).min(other.x + other.w) - self.x.max(other.x);
+        let h = (self.y + self.h).min(other.y + other.h) - self.y.max(other.y);
+        let intersection = w.max(0.0) * h.max(0.0);
+        let union = self.area() + other.area() - intersection;
+        if union > 0.0 { intersection / union } else { 0.0 }
+    }
+}
+
+/// An object found in an image.
+#[derive(Clone, Debug)]
+pub struct Detection {
+    pub label: String,
+    pub score: f32,
+    pub bbox: BoundingBox,
+}
+
+/// Greedy non-maximum suppression: keeps the highest-scoring box, drops every
+/// box overlapping it by more than `max_iou`, and repeats. Returns the indices
+/// kept, best first.
+pub fn non_max_suppression(boxes: &[BoundingBox], scores: &[f32], max_iou: f32) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..boxes.len().min(scores.len())).collect();
+    order.sort_by(|&a, &b| scores[b].partial_cmp(&scores[a]).unwrap_or(std::cmp::Ordering::Equal));
+    let mut kept: Vec<usize> = Vec::new();
+    for i in order {
+        if kept.iter().all(|&k| boxes[k].iou(&boxes[i]) <= max_iou) {
+            kept.push(i);
+        }
+    }
+    kept
+}
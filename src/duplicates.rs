@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+
+use crate::app::AppMessage;
+use crate::db::{self, HashedImage};
+use crate::phash;
+use crate::tagger::DecodedImage;
+
+/// Bits two images may differ in and still count as the same picture. Low
+/// enough to keep different shots of a scene apart, high enough for
+/// re-saves and bursts.
+pub const DEFAULT_MAX_DISTANCE: u32 = 6;
+
+/// Copies of one picture: identical files, re-encodes or burst shots.
+#[derive(Clone, Debug)]
+pub struct DuplicateGroup {
+    /// Largest resolution first, then largest file.
+    pub images: Vec<HashedImage>,
+    /// Every file has the same content.
+    pub exact: bool,
+}
+
+/// Groups images with the same content, or whose dHash and pHash both differ
+/// in at most `max_distance` bits. Every pair in a group is that close, so a
+/// burst whose first and last shots drift apart splits rather than chaining
+/// different pictures together. Groups form around the best copies first.
+pub fn find_groups(images: Vec<HashedImage>, max_distance: u32) -> Vec<DuplicateGroup> {
+    // Identical files always belong together and compare as one
+    let mut units: Vec<Vec<HashedImage>> = Vec::new();
+    let mut by_content: HashMap<String, usize> = HashMap::new();
+    for image in images {
+        match image.hash.as_ref().and_then(|hash| by_content.get(hash)).copied() {
+            Some(unit) => units[unit].push(image),
+            None => {
+                if let Some(hash) = &image.hash {
+                    by_content.insert(hash.clone(), units.len());
+                }
+                units.push(vec![image]);
+            }
+        }
+    }
+    for unit in &mut units {
+        unit.sort_by(best_first);
+    }
+    units.sort_by(|a, b| best_first(&a[0], &b[0]));
+
+    let mut tree = BkTree::default();
+    for (i, unit) in units.iter().enumerate() {
+        tree.insert(unit[0].hashes.phash, i);
+    }
+    let close = |a: &HashedImage, b: &HashedImage| {
+        phash::distance(a.hashes.phash, b.hashes.phash) <= max_distance
+            && phash::distance(a.hashes.dhash, b.hashes.dhash) <= max_distance
+    };
+
+    let mut assigned = vec![false; units.len()];
+    let mut groups = Vec::new();
+    for leader in 0..units.len() {
+        if assigned[leader] {
+            continue;
+        }
+        assigned[leader] = true;
+        let mut candidates = tree.within(units[leader][0].hashes.phash, max_distance);
+        candidates.retain(|&i| !assigned[i]);
+        // Units are sorted, so better copies join first
+        candidates.sort_unstable();
+        let mut members = vec![leader];
+        for candidate in candidates {
+            if members.iter().all(|&m| close(&units[m][0], &units[candidate][0])) {
+                assigned[candidate] = true;
+                members.push(candidate);
+            }
+        }
+        let mut images: Vec<HashedImage> = members.iter().flat_map(|&m| std::mem::take(&mut units[m])).collect();
+        if images.len() > 1 {
+            images.sort_by(best_first);
+            let exact = images.iter().all(|image| image.hash.is_some() && image.hash == images[0].hash);
+            groups.push(DuplicateGroup { images, exact });
+        }
+    }
+    groups.sort_by(|a, b| b.images.len().cmp(&a.images.len()).then_with(|| a.images[0].path.cmp(&b.images[0].path)));
+    groups
+}
+
+/// Largest resolution first, then largest file, then by path.
+fn best_first(a: &HashedImage, b: &HashedImage) -> std::cmp::Ordering {
+    let key = |image: &HashedImage| (image.hashes.width as u64 * image.hashes.height as u64, image.size.unwrap_or(0));
+    key(b).cmp(&key(a)).then_with(|| a.path.cmp(&b.path))
+}
+
+/// Burkhard-Keller tree over pHashes. Finding the images within a few bits of
+/// one visits a small part of the library instead of all of it.
+#[derive(Default)]
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: u64,
+    item: usize,
+    /// Children by their distance to this node.
+    children: Vec<(u32, usize)>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: u64, item: usize) {
+        let node = BkNode { hash, item, children: Vec::new() };
+        if self.nodes.is_empty() {
+            self.nodes.push(node);
+            return;
+        }
+        let mut i = 0;
+        loop {
+            let d = phash::distance(self.nodes[i].hash, hash);
+            match self.nodes[i].children.iter().find(|&&(child_d, _)| child_d == d) {
+                Some(&(_, child)) => i = child,
+                None => {
+                    let index = self.nodes.len();
+                    self.nodes.push(node);
+                    self.nodes[i].children.push((d, index));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Items whose hash is within `max` bits of `hash`.
+    fn within(&self, hash: u64, max: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut stack = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            let d = phash::distance(node.hash, hash);
+            if d <= max {
+                found.push(node.item);
+            }
+            // By the triangle inequality, only these subtrees can hold matches
+            stack.extend(node.children.iter().filter(|&&(child_d, _)| child_d.abs_diff(d) <= max).map(|&(_, c)| c));
+        }
+        found
+    }
+}
+
+/// Hashes images indexed before perceptual hashes existed, then groups the
+/// library (or `root`) and sends the result.
+pub fn run_duplicate_scan(root: Option<i64>, max_distance: u32, sender: mpsc::Sender<AppMessage>) {
+    let db_conn = match db::setup_db() {
+        Ok(c) => c,
+        Err(e) => {
+            sender.send(AppMessage::Log(format!("DB Error: {}", e))).ok();
+            sender.send(AppMessage::Duplicates(Vec::new())).ok();
+            return;
+        }
+    };
+
+    let unhashed = db::get_unhashed_images(&db_conn, root).unwrap_or_default();
+    if !unhashed.is_empty() {
+        sender.send(AppMessage::Log(format!("Hashing {} images indexed earlier...", unhashed.len()))).ok();
+    }
+    for path in unhashed {
+        let stored = DecodedImage::open(&path)
+            .and_then(|image| Ok(db::store_image_hashes(&db_conn, &path, phash::image_hashes(&image))?));
+        if let Err(e) = stored {
+            sender.send(AppMessage::Log(format!("Could not hash {}: {}", path.display(), e))).ok();
+        }
+    }
+
+    let groups = match db::get_hashed_images(&db_conn, root) {
+        Ok(images) => find_groups(images, max_distance),
+        Err(e) => {
+            sender.send(AppMessage::Log(format!("DB Error: {}", e))).ok();
+            Vec::new()
+        }
+    };
+    sender.send(AppMessage::Duplicates(groups)).ok();
+}
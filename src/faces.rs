@@ -0,0 +1,208 @@
+use image::DynamicImage;
+use image::imageops::FilterType;
+use ort::session::Session;
+use ort::value::Tensor;
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::db;
+use crate::detection::{self, BoundingBox};
+use crate::ml::{self, TaggingConfig};
+use crate::tagger::{DecodedImage, Tagger, TaggerOutput};
+use crate::tags::{ScoredTag, TagSource};
+
+/// Identifier recorded with the faces the models find.
+pub const FACE_MODEL: &str = "ultraface-arcface";
+
+/// UltraFace RFB-320 detector and an ArcFace-style 112x112 embedding model.
+pub const FACE_FILES: [&str; 2] = ["face-detector.onnx", "face-embedding.onnx"];
+
+/// Detector input, width by height.
+const DETECTOR_SIZE: (u32, u32) = (320, 240);
+const MIN_SCORE: f32 = 0.7;
+const MAX_OVERLAP: f32 = 0.3;
+/// Faces narrower than this share of the image are too small to recognise.
+const MIN_FACE_WIDTH: f32 = 0.03;
+const EMBEDDING_SIDE: u32 = 112;
+
+/// Cosine similarity from which a face joins a person.
+const SAME_PERSON: f32 = 0.45;
+
+/// Prefix of the tags that name people.
+pub const PERSON_PREFIX: &str = "person:";
+
+/// A face found in an image.
+pub struct Face {
+    pub bbox: BoundingBox,
+    pub score: f32,
+    /// Unit length, so faces compare by dot product.
+    pub embedding: Vec<f32>,
+}
+
+/// Finds faces and embeds each one. Clustering the embeddings into people
+/// happens after a tagging run, in `cluster_faces`.
+pub struct FaceModel {
+    detector: Mutex<Session>,
+    embedder: Mutex<Session>,
+}
+
+impl FaceModel {
+    pub fn load() -> anyhow::Result<Self> {
+        let [detector, embedder] = FACE_FILES.map(|file| {
+            ml::find_model_file(file).ok_or_else(|| anyhow::anyhow!("{} not found", file))
+        });
+        Ok(Self {
+            detector: Mutex::new(Session::builder()?.commit_from_file(detector?)?),
+            embedder: Mutex::new(Session::builder()?.commit_from_file(embedder?)?),
+        })
+    }
+
+    pub fn find_faces(&self, image: &DecodedImage) -> anyhow::Result<Vec<Face>> {
+        let boxes = self.detect(&image.preview)?;
+        let mut faces = Vec::with_capacity(boxes.len());
+        for (bbox, score) in boxes {
+            let crop = face_crop(&image.image, bbox);
+            faces.push(Face { bbox, score, embedding: self.embed(&crop)? });
+        }
+        Ok(faces)
+    }
+
+    fn detect(&self, img: &DynamicImage) -> anyhow::Result<Vec<(BoundingBox, f32)>> {
+        let (w, h) = DETECTOR_SIZE;
+        let rgb = img.resize_exact(w, h, FilterType::Triangle).to_rgb8();
+        let tensor = Tensor::from_array((vec![1, 3, h as usize, w as usize], planes(&rgb, 127.0, 128.0).into_boxed_slice()))?;
+
+        let mut session = self.detector.lock().map_err(|_| anyhow::anyhow!("Failed to lock session"))?;
+        let outputs = session.run(ort::inputs![tensor])?;
+        let (_shape, scores) = outputs.get("scores").unwrap_or(&outputs[0]).try_extract_tensor::<f32>()?;
+        let (_shape, corners) = outputs.get("boxes").unwrap_or(&outputs[1]).try_extract_tensor::<f32>()?;
+
+        // Scores are (background, face) pairs, boxes normalized corners
+        let (mut boxes, mut kept_scores) = (Vec::new(), Vec::new());
+        for (pair, c) in scores.chunks_exact(2).zip(corners.chunks_exact(4)) {
+            let bbox = BoundingBox::from_corners(c[0], c[1], c[2], c[3]);
+            if pair[1] >= MIN_SCORE && bbox.w >= MIN_FACE_WIDTH {
+                boxes.push(bbox);
+                kept_scores.push(pair[1]);
+            }
+        }
+        Ok(detection::non_max_suppression(&boxes, &kept_scores, MAX_OVERLAP)
+            .into_iter()
+            .map(|i| (boxes[i], kept_scores[i]))
+            .collect())
+    }
+
+    fn embed(&self, face: &DynamicImage) -> anyhow::Result<Vec<f32>> {
+        let side = EMBEDDING_SIDE as usize;
+        let rgb = face.resize_exact(EMBEDDING_SIDE, EMBEDDING_SIDE, FilterType::Triangle).to_rgb8();
+        let tensor = Tensor::from_array((vec![1, 3, side, side], planes(&rgb, 127.5, 127.5).into_boxed_slice()))?;
+        let mut session = self.embedder.lock().map_err(|_| anyhow::anyhow!("Failed to lock session"))?;
+        let outputs = session.run(ort::inputs![tensor])?;
+        let (_shape, data) = outputs[0].try_extract_tensor::<f32>()?;
+        Ok(ml::normalized(data))
+    }
+}
+
+impl Tagger for FaceModel {
+    fn name(&self) -> &str {
+        FACE_MODEL
+    }
+
+    fn version(&self) -> &str {
+        "1"
+    }
+
+    fn source(&self) -> TagSource {
+        TagSource::Person
+    }
+
+    /// People are tagged once a face cluster is named, not per image.
+    fn tag(&self, _image: &DecodedImage, _config: &TaggingConfig) -> anyhow::Result<Vec<ScoredTag>> {
+        Ok(Vec::new())
+    }
+
+    fn analyze(&self, image: &DecodedImage, _config: &TaggingConfig) -> anyhow::Result<TaggerOutput> {
+        Ok(TaggerOutput { faces: Some(self.find_faces(image)?), ..Default::default() })
+    }
+}
+
+/// The face with some margin, squared up so it is not stretched.
+fn face_crop(img: &DynamicImage, bbox: BoundingBox) -> DynamicImage {
+    let (w, h) = (img.width() as f32, img.height() as f32);
+    let (cx, cy) = ((bbox.x + bbox.w / 2.0) * w, (bbox.y + bbox.h / 2.0) * h);
+    let side = (bbox.w * w).max(bbox.h * h) * 1.2;
+    let x = (cx - side / 2.0).clamp(0.0, w - 1.0);
+    let y = (cy - side / 2.0).clamp(0.0, h - 1.0);
+    let cw = side.min(w - x).max(1.0);
+    let ch = side.min(h - y).max(1.0);
+    img.crop_imm(x as u32, y as u32, cw as u32, ch as u32)
+}
+
+/// Channel planes, `(value - mean) / scale`.
+fn planes(rgb: &image::RgbImage, mean: f32, scale: f32) -> Vec<f32> {
+    let mut input = Vec::with_capacity(rgb.len());
+    for c in 0..3 {
+        input.extend(rgb.pixels().map(|pixel| (pixel[c] as f32 - mean) / scale));
+    }
+    input
+}
+
+/// Puts every face without a person into the most similar person, by the
+/// average of their faces, or into a new unnamed person. Returns the images
+/// that gained a named person, whose tags need refreshing.
+pub fn cluster_faces(conn: &Connection) -> rusqlite::Result<Vec<PathBuf>> {
+    let faces = db::get_faces(conn)?;
+    let named: HashMap<i64, String> = db::get_people(conn)?
+        .into_iter()
+        .filter_map(|person| person.name.map(|name| (person.id, name)))
+        .collect();
+
+    let mut centroids: HashMap<i64, Vec<f32>> = HashMap::new();
+    for face in &faces {
+        if let Some(person) = face.person_id {
+            let sum = centroids.entry(person).or_insert_with(|| vec![0.0; face.embedding.len()]);
+            sum.iter_mut().zip(&face.embedding).for_each(|(s, x)| *s += x);
+        }
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    let mut changed = Vec::new();
+    for face in faces.iter().filter(|face| face.person_id.is_none()) {
+        let best = centroids.iter()
+            .map(|(&person, sum)| (person, similarity(sum, &face.embedding)))
+            .filter(|&(_, similarity)| similarity >= SAME_PERSON)
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        let person = match best {
+            Some((person, _)) => person,
+            None => db::create_person(&tx)?,
+        };
+        db::set_face_person(&tx, face.id, person)?;
+        let sum = centroids.entry(person).or_insert_with(|| vec![0.0; face.embedding.len()]);
+        sum.iter_mut().zip(&face.embedding).for_each(|(s, x)| *s += x);
+        if named.contains_key(&person) {
+            changed.push(face.path.clone());
+        }
+    }
+    db::remove_empty_people(&tx)?;
+    tx.commit()?;
+    changed.sort();
+    changed.dedup();
+    Ok(changed)
+}
+
+/// Cosine similarity of a unit vector to the direction of a sum of them.
+fn similarity(sum: &[f32], unit: &[f32]) -> f32 {
+    let norm = sum.iter().map(|x| x * x).sum::<f32>().sqrt().max(f32::EPSILON);
+    sum.iter().zip(unit).map(|(a, b)| a * b).sum::<f32>() / norm
+}
+
+/// Replaces the `person:` tags of `path` with the named people in it.
+pub fn refresh_person_tags(conn: &Connection, path: &Path) -> rusqlite::Result<()> {
+    let tags: Vec<ScoredTag> = db::get_person_names(conn, path)?
+        .into_iter()
+        .map(|name| ScoredTag::new(format!("{}{}", PERSON_PREFIX, name), 1.0, TagSource::Person, None))
+        .collect();
+    db::store_tags(conn, path, TagSource::Person, None, &tags, None)
+}
@@ -0,0 +1,253 @@
+use rusqlite::Connection;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dates;
+use crate::db::{self, HashedImage};
+use crate::duplicates::DuplicateGroup;
+use crate::paths;
+
+/// Which copy of a duplicate group stays where it is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeepRule {
+    Largest,
+    HighestResolution,
+    Oldest,
+}
+
+impl KeepRule {
+    pub const ALL: [KeepRule; 3] = [KeepRule::Largest, KeepRule::HighestResolution, KeepRule::Oldest];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            KeepRule::Largest => "Keep largest file",
+            KeepRule::HighestResolution => "Keep highest resolution",
+            KeepRule::Oldest => "Keep oldest",
+        }
+    }
+
+    /// The keeper among the copies that are reachable right now.
+    fn pick(self, images: &[HashedImage]) -> Option<&HashedImage> {
+        let online = images.iter().filter(|image| !image.offline);
+        match self {
+            KeepRule::Largest => online.max_by_key(|image| image.size.unwrap_or(0)),
+            KeepRule::HighestResolution => {
+                online.max_by_key(|image| image.hashes.width as u64 * image.hashes.height as u64)
+            }
+            KeepRule::Oldest => online.min_by_key(|image| image.mtime.unwrap_or(i64::MAX)),
+        }
+    }
+}
+
+/// Where the copies that are not kept go.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Destination {
+    /// The freedesktop trash, where file managers can restore them too.
+    Trash,
+    /// A folder to review before deleting by hand.
+    Quarantine(PathBuf),
+}
+
+impl Destination {
+    pub fn label(&self) -> String {
+        match self {
+            Destination::Trash => "Trash".to_string(),
+            Destination::Quarantine(dir) => dir.display().to_string(),
+        }
+    }
+
+    /// Recorded in the undo log.
+    fn action(&self) -> &'static str {
+        match self {
+            Destination::Trash => "trash",
+            Destination::Quarantine(_) => "quarantine",
+        }
+    }
+}
+
+/// `$XDG_DATA_HOME/local_lens/quarantine`
+pub fn default_quarantine() -> Option<PathBuf> {
+    paths::data_dir().map(|dir| dir.join("quarantine"))
+}
+
+/// What resolving duplicate groups will do, shown before anything moves.
+pub struct ResolvePlan {
+    pub keep: Vec<PathBuf>,
+    pub remove: Vec<PathBuf>,
+    pub destination: Destination,
+}
+
+/// Keeps one copy per group by `rule` and moves the others. Copies on drives
+/// that are not mounted stay where they are.
+pub fn plan(groups: &[&DuplicateGroup], rule: KeepRule, destination: Destination) -> ResolvePlan {
+    let mut plan = ResolvePlan { keep: Vec::new(), remove: Vec::new(), destination };
+    for group in groups {
+        let Some(keeper) = rule.pick(&group.images) else {
+            continue;
+        };
+        plan.keep.push(keeper.path.clone());
+        plan.remove.extend(
+            group.images.iter()
+                .filter(|image| !image.offline && image.path != keeper.path)
+                .map(|image| image.path.clone()),
+        );
+    }
+    plan
+}
+
+/// Moves the files `plan` removes and logs every move so `undo_last` can put
+/// it back. The log entry is written before the file moves, so nothing moves
+/// without one. Moved files are hidden from the index but keep their tags.
+/// Returns how many moved and what went wrong with the rest.
+pub fn execute(conn: &Connection, plan: &ResolvePlan) -> anyhow::Result<(usize, Vec<String>)> {
+    let batch = db::next_file_op_batch(conn)?;
+    let mut moved = 0;
+    let mut errors = Vec::new();
+    for path in &plan.remove {
+        let (target, trash_info) = match reserve_target(path, &plan.destination) {
+            Ok(reserved) => reserved,
+            Err(e) => {
+                errors.push(format!("{}: {}", path.display(), e));
+                continue;
+            }
+        };
+        let logged = db::record_file_op(conn, batch, plan.destination.action(), path, &target, trash_info.as_deref());
+        let id = match logged {
+            Ok(id) => id,
+            Err(e) => {
+                if let Some(info) = &trash_info {
+                    fs::remove_file(info).ok();
+                }
+                return Err(e.into());
+            }
+        };
+        match move_file(path, &target) {
+            Ok(()) => {
+                db::set_moved_away(conn, path, true)?;
+                moved += 1;
+            }
+            Err(e) => {
+                db::delete_file_op(conn, id)?;
+                if let Some(info) = &trash_info {
+                    fs::remove_file(info).ok();
+                }
+                errors.push(format!("{}: {}", path.display(), e));
+            }
+        }
+    }
+    Ok((moved, errors))
+}
+
+/// Puts back the files of the latest resolution that was not undone yet,
+/// with their tags and faces as they were.
+pub fn undo_last(conn: &Connection) -> anyhow::Result<(usize, Vec<String>)> {
+    let mut restored = 0;
+    let mut errors = Vec::new();
+    for op in db::get_last_file_ops(conn)? {
+        if op.original.exists() {
+            errors.push(format!("{}: another file is in the way", op.original.display()));
+            continue;
+        }
+        let result = op.original.parent().map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| move_file(&op.moved_to, &op.original));
+        match result {
+            Ok(()) => {
+                if let Some(info) = &op.trash_info {
+                    fs::remove_file(info).ok();
+                }
+                db::set_moved_away(conn, &op.original, false)?;
+                db::mark_file_op_undone(conn, op.id)?;
+                restored += 1;
+            }
+            Err(e) => errors.push(format!("{}: {}", op.original.display(), e)),
+        }
+    }
+    Ok((restored, errors))
+}
+
+/// Picks a free name for `path` in `destination` without moving it yet.
+/// Returns the target and, for the trash, the `.trashinfo` file already
+/// written for it.
+fn reserve_target(path: &Path, destination: &Destination) -> io::Result<(PathBuf, Option<PathBuf>)> {
+    let name = path.file_name().ok_or_else(|| io::Error::other("not a file"))?;
+    match destination {
+        Destination::Trash => {
+            let trash = paths::trash_dir().ok_or_else(|| io::Error::other("no trash directory"))?;
+            let (files, info) = (trash.join("files"), trash.join("info"));
+            fs::create_dir_all(&files)?;
+            fs::create_dir_all(&info)?;
+            // Creating the info file first reserves the name, as the spec asks
+            let mut n = 0;
+            loop {
+                let candidate = numbered(name, n);
+                let info_path = info.join(format!("{}.trashinfo", candidate.to_string_lossy()));
+                let target = files.join(&candidate);
+                if !target.exists() {
+                    match OpenOptions::new().write(true).create_new(true).open(&info_path) {
+                        Ok(mut file) => {
+                            write!(file, "[Trash Info]\nPath={}\nDeletionDate={}\n", encode_path(path), deletion_date())?;
+                            return Ok((target, Some(info_path)));
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                n += 1;
+            }
+        }
+        Destination::Quarantine(dir) => {
+            fs::create_dir_all(dir)?;
+            let target = (0..).map(|n| dir.join(numbered(name, n))).find(|p| !p.exists()).unwrap_or_default();
+            Ok((target, None))
+        }
+    }
+}
+
+/// `name` for 0, then `stem.1.ext`, `stem.2.ext` and so on.
+fn numbered(name: &std::ffi::OsStr, n: u32) -> PathBuf {
+    if n == 0 {
+        return PathBuf::from(name);
+    }
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or(name).to_string_lossy();
+    match path.extension() {
+        Some(ext) => PathBuf::from(format!("{}.{}.{}", stem, n, ext.to_string_lossy())),
+        None => PathBuf::from(format!("{}.{}", stem, n)),
+    }
+}
+
+/// Renames, or copies and deletes when the destination is on another drive.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Percent-encodes an absolute path for the `Path=` key. Raw bytes, so names
+/// that are not UTF-8 still match the file.
+fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Now as `YYYY-MM-DDThh:mm:ss`. The spec wants local time; UTC is close
+/// enough for sorting the trash and avoids a time zone database.
+fn deletion_date() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let (days, rest) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let (year, month, day) = dates::civil_from_days(days);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", year, month, day, rest / 3600, rest % 3600 / 60, rest % 60)
+}
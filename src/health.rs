@@ -0,0 +1,256 @@
+use ort::session::Session;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use crate::app::AppMessage;
+use crate::clip;
+use crate::faces;
+use crate::manifest::{self, Layout, ModelManifest};
+use crate::ml;
+use crate::objects;
+use crate::ocr;
+
+const DOWNLOAD_HINT: &str = "Run ./download-models.sh and restart, or copy the file to ~/.local/share/local_lens/models.";
+
+/// Known digests of the model files, by file name.
+const CHECKSUMS: &str = include_str!("../models/checksums.sha256");
+
+/// Tensor dimensions; -1 accepts any size.
+type Shape = &'static [i64];
+
+/// Input and output shapes the code relies on, by file name. A model's own
+/// dynamic dimensions match anything.
+const EXPECTED_SHAPES: &[(&str, Shape, &[Shape])] = &[
+    ("det_model.onnx", &[-1, 3, -1, -1], &[&[-1, 1, -1, -1]]),
+    ("rec_model.onnx", &[-1, 3, -1, -1], &[&[-1, -1, -1]]),
+    ("clip-image.onnx", &[-1, 3, -1, -1], &[&[-1, -1]]),
+    ("clip-text.onnx", &[-1, -1], &[&[-1, -1]]),
+    ("face-detector.onnx", &[-1, 3, 240, 320], &[&[-1, -1, 2], &[-1, -1, 4]]),
+    ("face-embedding.onnx", &[-1, 3, 112, 112], &[&[-1, -1]]),
+    ("yolo-detector.onnx", &[-1, 3, -1, -1], &[&[-1, -1, -1]]),
+];
+
+/// Outcome of checking one model.
+#[derive(Clone, Debug)]
+pub struct ModelStatus {
+    pub name: String,
+    pub version: String,
+    pub path: Option<PathBuf>,
+    /// What is wrong and how to fix it. Empty when the model is usable.
+    pub problems: Vec<String>,
+}
+
+impl ModelStatus {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks every classifier manifest, the OCR files and the optional CLIP and
+/// face models, if installed, and reports the result.
+/// Loads each model once, so it runs on a background thread.
+pub fn run_model_check(sender: mpsc::Sender<AppMessage>) {
+    let mut statuses = Vec::new();
+    for manifest in manifest::discover() {
+        statuses.push(match manifest {
+            Ok(manifest) => check_classifier(&manifest),
+            Err(e) => ModelStatus {
+                name: "Invalid manifest".to_string(),
+                version: String::new(),
+                path: None,
+                problems: vec![format!("{}. Fix the JSON or remove the file.", e)],
+            },
+        });
+    }
+    if statuses.is_empty() {
+        statuses.push(ModelStatus {
+            name: "Classifier".to_string(),
+            version: String::new(),
+            path: None,
+            problems: vec![format!("No classifier found. {}", DOWNLOAD_HINT)],
+        });
+    }
+    statuses.push(check_ocr());
+    statuses.extend(check_optional(clip::CLIP_MODEL, &clip::CLIP_FILES, "semantic search"));
+    statuses.extend(check_optional(faces::FACE_MODEL, &faces::FACE_FILES, "face recognition"));
+    statuses.extend(check_optional(objects::DETECTOR_MODEL, &objects::DETECTOR_FILES, "object detection"));
+    sender.send(AppMessage::ModelHealth(statuses)).ok();
+}
+
+fn check_classifier(manifest: &ModelManifest) -> ModelStatus {
+    let path = manifest.model_path();
+    let mut status = ModelStatus {
+        name: manifest.name.clone(),
+        version: manifest.version.clone(),
+        path: Some(path.clone()),
+        problems: Vec::new(),
+    };
+    if !path.exists() {
+        status.problems.push(format!("{} not found. {}", manifest.file, DOWNLOAD_HINT));
+        return status;
+    }
+
+    let expected = manifest.sha256.as_deref().or_else(|| known_digest(&manifest.file));
+    status.problems.extend(check_digest(&path, expected));
+    status.problems.extend(check_digest(&manifest.labels_path(), known_digest(&manifest.labels)));
+
+    let labels = match ml::load_labels(&manifest.labels_path()) {
+        Ok(labels) => Some(labels.len()),
+        Err(e) => {
+            status.problems.push(format!("Labels file {}: {}. {}", manifest.labels, e, DOWNLOAD_HINT));
+            None
+        }
+    };
+
+    let session = match Session::builder().and_then(|b| b.commit_from_file(&path)) {
+        Ok(session) => session,
+        Err(e) => {
+            status.problems.push(format!("Not a loadable ONNX model ({}). Download it again.", e));
+            return status;
+        }
+    };
+    if let Some(shape) = session.inputs().first().and_then(|i| i.dtype().tensor_shape()) {
+        let fits = shape.len() == 4
+            && shape.iter().zip(manifest.input_shape).all(|(&model, wanted)| model == -1 || wanted == -1 || model == wanted);
+        if !fits {
+            let layout = match manifest.layout {
+                Layout::Nchw => "nchw",
+                Layout::Nhwc => "nhwc",
+            };
+            status.problems.push(format!(
+                "Model input is {:?} but the manifest says {:?} ({}). Fix input_shape or layout in the manifest.",
+                &shape[..], manifest.input_shape, layout
+            ));
+        }
+    }
+    let classes = session.outputs().first()
+        .and_then(|o| o.dtype().tensor_shape())
+        .and_then(|shape| shape.last().copied())
+        .filter(|&n| n > 0);
+    match (classes, labels) {
+        (Some(classes), Some(labels)) if classes as usize != labels => status.problems.push(format!(
+            "Model has {} outputs but {} lists {} labels. Use the label file that belongs to this model.",
+            classes, manifest.labels, labels
+        )),
+        _ => {}
+    }
+    status
+}
+
+fn check_ocr() -> ModelStatus {
+    let mut status = ModelStatus {
+        name: ocr::OCR_MODEL.to_string(),
+        version: "1".to_string(),
+        path: None,
+        problems: Vec::new(),
+    };
+    for file in ocr::OCR_FILES {
+        let Some(path) = ml::find_model_file(file) else {
+            status.problems.push(format!("{} not found, text search is off. {}", file, DOWNLOAD_HINT));
+            continue;
+        };
+        status.problems.extend(check_file(file, &path, "Download it again."));
+        status.path.get_or_insert_with(|| path.parent().map(Path::to_path_buf).unwrap_or_default());
+    }
+    // The files can each look fine and still not make a working engine
+    if status.problems.is_empty()
+        && let Err(e) = ocr::OcrModel::load()
+    {
+        status.problems.push(e.to_string());
+    }
+    status
+}
+
+/// Optional models are only reported once some of their files exist.
+fn check_optional(name: &str, files: &[&str], feature: &str) -> Option<ModelStatus> {
+    let found: Vec<_> = files.iter().map(|file| (file, ml::find_model_file(file))).collect();
+    let path = found.iter().find_map(|(_, path)| path.as_deref()?.parent().map(Path::to_path_buf));
+    path.as_ref()?;
+    let mut status = ModelStatus {
+        name: name.to_string(),
+        version: "1".to_string(),
+        path,
+        problems: Vec::new(),
+    };
+    for (file, path) in found {
+        match path {
+            None => status.problems.push(format!("{} not found, {} is off.", file, feature)),
+            Some(path) => status.problems.extend(check_file(file, &path, "Export it again.")),
+        }
+    }
+    Some(status)
+}
+
+/// Verifies the digest of a file found by `ml::find_model_file` and, for ONNX
+/// models, that it loads and has the shapes the code expects.
+fn check_file(file: &str, path: &Path, fix: &str) -> Vec<String> {
+    let mut problems: Vec<String> = check_digest(path, known_digest(file)).into_iter().collect();
+    if !file.ends_with(".onnx") {
+        return problems;
+    }
+    let session = match Session::builder().and_then(|b| b.commit_from_file(path)) {
+        Ok(session) => session,
+        Err(e) => {
+            problems.push(format!("{} is not a loadable ONNX model ({}). {}", file, e, fix));
+            return problems;
+        }
+    };
+    let Some(&(_, input, outputs)) = EXPECTED_SHAPES.iter().find(|(name, _, _)| *name == file) else {
+        return problems;
+    };
+    let input_shape = session.inputs().first().and_then(|i| i.dtype().tensor_shape()).map(|s| s.to_vec());
+    if let Some(shape) = input_shape.filter(|shape| !shape_fits(shape, input)) {
+        problems.push(format!("{} takes input {:?}, expected {:?}. {}", file, shape, input, fix));
+    }
+    if session.outputs().len() < outputs.len() {
+        problems.push(format!("{} has {} outputs, expected {}. {}", file, session.outputs().len(), outputs.len(), fix));
+    }
+    for (output, expected) in session.outputs().iter().zip(outputs) {
+        match output.dtype().tensor_shape() {
+            Some(shape) if !shape_fits(shape, expected) => problems.push(format!(
+                "{} output {} is {:?}, expected {:?}. {}",
+                file, output.name(), &shape[..], expected, fix
+            )),
+            _ => {}
+        }
+    }
+    problems
+}
+
+/// Same rank, and equal sizes wherever neither side is dynamic.
+fn shape_fits(shape: &[i64], expected: &[i64]) -> bool {
+    shape.len() == expected.len()
+        && shape.iter().zip(expected).all(|(&model, &wanted)| model == -1 || wanted == -1 || model == wanted)
+}
+
+/// Digest of `file` in the bundled checksum list.
+fn known_digest(file: &str) -> Option<&'static str> {
+    CHECKSUMS.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, name)| name.trim_start_matches([' ', '*']) == file)
+        .map(|(digest, _)| digest)
+}
+
+fn check_digest(path: &Path, expected: Option<&str>) -> Option<String> {
+    let expected = expected?;
+    match sha256_file(path) {
+        Ok(actual) if actual.eq_ignore_ascii_case(expected) => None,
+        Ok(actual) => Some(format!(
+            "{}: checksum mismatch (got {}…). The file is damaged or a different version; delete it and download it again.",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            &actual[..12]
+        )),
+        Err(e) => Some(format!("Could not read {}: {}", path.display(), e)),
+    }
+}
+
+/// Hex SHA-256 of a file.
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
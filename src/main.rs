@@ -1,14 +1,70 @@
 mod app;
+mod clip;
+mod colors;
+mod dates;
 mod db;
+mod detection;
+mod duplicates;
+mod faces;
+mod file_ops;
+mod health;
+mod manifest;
 mod ml;
+mod paths;
+mod phash;
 mod scanner;
+mod objects;
 mod ocr;
 mod processing;
+mod query;
+mod tagger;
+mod tags;
+mod thumbnails;
 
 use app::ImageTagger;
 use eframe::NativeOptions;
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: local_lens [--catalog <file.db>]
+
+Options:
+  --catalog <file.db>  Open this catalog instead of the default
+                       ($XDG_DATA_HOME/local_lens/image_tags.db)
+  -h, --help           Show this help";
+
+fn parse_catalog_arg() -> Option<PathBuf> {
+    let mut args = std::env::args().skip(1);
+    let mut catalog = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "--catalog" => match args.next() {
+                Some(path) => catalog = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("--catalog needs a file path\n\n{}", USAGE);
+                    std::process::exit(2);
+                }
+            },
+            _ => match arg.strip_prefix("--catalog=") {
+                Some(path) => catalog = Some(PathBuf::from(path)),
+                None => eprintln!("Ignoring unknown argument: {}", arg),
+            },
+        }
+    }
+    catalog
+}
 
 fn main() -> eframe::Result<()> {
+    // Command line beats the catalog picked in the app last time
+    if let Some(catalog) = parse_catalog_arg().or_else(db::saved_catalog) {
+        db::set_db_path(catalog);
+    } else if let Some(moved_to) = db::adopt_legacy_db() {
+        eprintln!("Moved catalog from the working directory to {}", moved_to.display());
+    }
+
     let options = NativeOptions::default();
     eframe::run_native(
         "Local Lens",
@@ -0,0 +1,182 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ml::{self, CropMode};
+
+/// Manifest for the bundled ResNet50, used when the models directory predates
+/// manifests and only has the `.onnx` file.
+const BUILTIN_RESNET50: &str = include_str!("../models/resnet50-v2-7.model.json");
+
+/// Suffix that marks a manifest in a models directory.
+const MANIFEST_SUFFIX: &str = ".model.json";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    #[default]
+    Nchw,
+    Nhwc,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelOrder {
+    #[default]
+    Rgb,
+    Bgr,
+}
+
+/// How the model's raw output turns into per-class probabilities.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Activation {
+    /// One label per image: probabilities sum to one.
+    #[default]
+    Softmax,
+    /// Independent probability per class.
+    Sigmoid,
+    /// The model already outputs probabilities.
+    Probabilities,
+}
+
+/// Describes an ONNX image classifier: where its files are and how images
+/// must be prepared for it. Lives next to the model as `<name>.model.json`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ModelManifest {
+    /// Identifier recorded with every tag the model produces.
+    pub name: String,
+    #[serde(default = "default_version")]
+    pub version: String,
+    /// Model file, relative to the manifest.
+    pub file: String,
+    /// Expected SHA-256 of `file`, hex encoded. Checked at startup.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Input tensor shape in `layout` order; -1 marks a dynamic batch.
+    pub input_shape: [i64; 4],
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default)]
+    pub channel_order: ChannelOrder,
+    /// Applied to pixels scaled to `0.0..=1.0`, per channel in `channel_order`.
+    pub mean: [f32; 3],
+    pub std: [f32; 3],
+    /// Shorter side the image is scaled to before cropping.
+    #[serde(default = "default_resize")]
+    pub resize: u32,
+    #[serde(default)]
+    pub crop: CropMode,
+    /// Label file, relative to the manifest: a JSON array of names or one
+    /// name per line.
+    pub labels: String,
+    #[serde(default)]
+    pub activation: Activation,
+    /// Whether an image can get several labels at once. Defaults to true for
+    /// sigmoid outputs.
+    #[serde(default)]
+    pub multi_label: Option<bool>,
+    /// Multi-label cut-off for classes without their own threshold.
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    /// Name of an extra model output with penultimate-layer features. When
+    /// set they are stored as the image's embedding for "More like this".
+    #[serde(default)]
+    pub embedding_output: Option<String>,
+
+    /// Directory the manifest was read from.
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+fn default_version() -> String {
+    "1".to_string()
+}
+
+fn default_resize() -> u32 {
+    256
+}
+
+fn default_threshold() -> f32 {
+    0.5
+}
+
+impl ModelManifest {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let mut manifest: Self = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        manifest.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(manifest)
+    }
+
+    pub fn model_path(&self) -> PathBuf {
+        self.resolve(&self.file)
+    }
+
+    pub fn labels_path(&self) -> PathBuf {
+        self.resolve(&self.labels)
+    }
+
+    /// Next to the manifest, or anywhere `find_model_file` looks.
+    fn resolve(&self, file: &str) -> PathBuf {
+        let local = self.dir.join(file);
+        if local.exists() {
+            return local;
+        }
+        ml::find_model_file(file).unwrap_or(local)
+    }
+
+    pub fn multi_label(&self) -> bool {
+        self.multi_label.unwrap_or(self.activation == Activation::Sigmoid)
+    }
+
+    /// Height and width of the input, whatever the layout.
+    pub fn input_size(&self) -> (u32, u32) {
+        let [_, a, b, c] = self.input_shape;
+        let (h, w) = match self.layout {
+            Layout::Nchw => (b, c),
+            Layout::Nhwc => (a, b),
+        };
+        (h.max(1) as u32, w.max(1) as u32)
+    }
+}
+
+/// Every manifest in the model directories. A name found in more than one
+/// directory is taken from the first, in the same order `find_model_file`
+/// searches. Manifests that fail to parse are returned as errors so the UI
+/// can show them.
+pub fn discover() -> Vec<anyhow::Result<ModelManifest>> {
+    let mut seen = HashSet::new();
+    let mut found = Vec::new();
+    for dir in ml::model_dirs() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.to_string_lossy().ends_with(MANIFEST_SUFFIX))
+            .collect();
+        paths.sort();
+        for path in paths {
+            match ModelManifest::from_file(&path) {
+                Ok(manifest) => {
+                    if seen.insert(manifest.name.clone()) {
+                        found.push(Ok(manifest));
+                    }
+                }
+                Err(e) => found.push(Err(e)),
+            }
+        }
+    }
+
+    // Installs from before manifests only have the model file
+    let builtin = serde_json::from_str::<ModelManifest>(BUILTIN_RESNET50).ok()
+        .filter(|builtin| !seen.contains(&builtin.name))
+        .and_then(|builtin| ml::find_model_file(&builtin.file).map(|model| (builtin, model)));
+    if let Some((mut builtin, model)) = builtin {
+        builtin.dir = model.parent().map(Path::to_path_buf).unwrap_or_default();
+        found.push(Ok(builtin));
+    }
+    found
+}
@@ -1,113 +1,456 @@
-use image::ImageReader;
+use image::DynamicImage;
 use ort::{inputs, session::Session, value::Value};
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-use std::env;
+use crate::manifest::{Activation, ChannelOrder, Layout, ModelManifest};
+use crate::paths;
+use crate::tagger::{DecodedImage, Tagger, TaggerOutput};
+use crate::tags::{ScoredTag, TagSource};
+
+/// Which crops of an image the classifier sees. With more than one crop the
+/// probabilities are averaged (test-time augmentation): slower, but steadier
+/// on busy or off-centre subjects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CropMode {
+    #[default]
+    Center,
+    /// Four corners plus the centre.
+    FiveCrop,
+    /// The five crops and their mirror images.
+    TenCrop,
+}
+
+impl CropMode {
+    pub const ALL: [CropMode; 3] = [CropMode::Center, CropMode::FiveCrop, CropMode::TenCrop];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CropMode::Center => "Center crop",
+            CropMode::FiveCrop => "5 crops",
+            CropMode::TenCrop => "10 crops",
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            CropMode::Center => 1,
+            CropMode::FiveCrop => 5,
+            CropMode::TenCrop => 10,
+        }
+    }
+}
+
+/// Controls which classifier predictions become tags.
+#[derive(Clone, Debug)]
+pub struct TaggingConfig {
+    /// Labels below this probability are dropped.
+    pub min_probability: f32,
+    /// Upper bound on labels kept per image.
+    pub max_tags: usize,
+    /// Stop once the kept labels account for this much probability mass.
+    pub cumulative_cutoff: f32,
+    /// Threads decoding image files ahead of inference.
+    pub decode_workers: usize,
+    /// Threads running the taggers, each with its own model session.
+    pub inference_workers: usize,
+    /// Images per inference call for models with a dynamic batch dimension.
+    pub batch_size: usize,
+    /// Crop mode chosen per classifier name; missing entries use the model's default.
+    pub crop_modes: HashMap<String, CropMode>,
+}
+
+impl Default for TaggingConfig {
+    fn default() -> Self {
+        Self {
+            min_probability: 0.1,
+            max_tags: 5,
+            cumulative_cutoff: 0.9,
+            decode_workers: std::thread::available_parallelism().map_or(2, |n| n.get().clamp(1, 4)),
+            inference_workers: 2,
+            batch_size: 8,
+            crop_modes: HashMap::new(),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Label {
     pub name: String,
+    /// Per-class cut-off for multi-label models, overriding the manifest's.
+    pub threshold: Option<f32>,
+}
+
+/// A label file entry: a bare name, or a name with its own threshold.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LabelEntry {
+    Name(String),
+    WithThreshold { name: String, threshold: Option<f32> },
+}
+
+/// Where model files are looked for, in order.
+pub fn model_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    // 1. Current working directory 'models/'
+    dirs.push(PathBuf::from("models"));
+    // 2. $XDG_DATA_HOME/local_lens/models (~/.local/share/local_lens/models)
+    if let Some(data_dir) = paths::data_dir() {
+        dirs.push(data_dir.join("models"));
+    }
+    // 3. /usr/share/local_lens/models
+    dirs.push(PathBuf::from("/usr/share/local_lens/models"));
+    dirs
 }
 
 pub fn find_model_file(filename: &str) -> Option<PathBuf> {
-    // 1. Check current working directory 'models/'
-    let cwd_path = Path::new("models").join(filename);
-    if cwd_path.exists() {
-        return Some(cwd_path);
+    model_dirs().into_iter()
+        .map(|dir| dir.join(filename))
+        .find(|path| path.exists())
+}
+
+/// Reads a JSON array of label names or `{"name", "threshold"}` objects, or a
+/// text file with one name per line, optionally followed by a tab and a
+/// threshold.
+pub fn load_labels(path: &Path) -> anyhow::Result<Vec<Label>> {
+    if path.extension().is_some_and(|e| e == "json") {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        let entries: Vec<LabelEntry> = serde_json::from_reader(reader)?;
+        return Ok(entries.into_iter().map(|entry| match entry {
+            LabelEntry::Name(name) => Label { name, threshold: None },
+            LabelEntry::WithThreshold { name, threshold } => Label { name, threshold },
+        }).collect());
     }
 
-    // 2. Check ~/.local/share/local_lens/models
-    if let Ok(home) = env::var("HOME") {
-         let share_path = PathBuf::from(home).join(".local/share/local_lens/models").join(filename);
-         if share_path.exists() {
-             return Some(share_path);
-         }
+    let text = std::fs::read_to_string(path)?;
+    Ok(text.lines().map(|line| {
+        let (name, threshold) = match line.split_once('\t') {
+            Some((name, threshold)) => (name, threshold.trim().parse().ok()),
+            None => (line, None),
+        };
+        Label { name: name.trim().to_string(), threshold }
+    }).collect())
+}
+
+/// An ONNX image classifier described by a model manifest.
+pub struct ClassifierTagger {
+    manifest: ModelManifest,
+    model_path: PathBuf,
+    /// Idle sessions. A worker takes one per image and a new one is created
+    /// when all are busy, so there are at most as many as inference workers.
+    sessions: Mutex<Vec<Session>>,
+    /// The model takes a batch of images per run.
+    batched: bool,
+    labels: Vec<Label>,
+}
+
+impl ClassifierTagger {
+    pub fn load(manifest: ModelManifest) -> anyhow::Result<Self> {
+        let model_path = manifest.model_path();
+        if !model_path.exists() {
+            anyhow::bail!("Model file {} not found", model_path.display());
+        }
+        // Build the first session now so a broken model shows up at startup
+        let session = Session::builder()?.commit_from_file(&model_path)?;
+        let labels = load_labels(&manifest.labels_path())
+            .map_err(|e| anyhow::anyhow!("Labels {}: {}", manifest.labels, e))?;
+        Ok(Self {
+            batched: has_dynamic_batch(&session),
+            sessions: Mutex::new(vec![session]),
+            model_path,
+            manifest,
+            labels,
+        })
     }
 
-    // 3. Check /usr/share/local_lens/models
-    let sys_path = Path::new("/usr/share/local_lens/models").join(filename);
-    if sys_path.exists() {
-        return Some(sys_path);
+    fn take_session(&self) -> anyhow::Result<Session> {
+        let idle = self.sessions.lock()
+            .map_err(|_| anyhow::anyhow!("Failed to lock session pool"))?
+            .pop();
+        match idle {
+            Some(session) => Ok(session),
+            None => Ok(Session::builder()?.commit_from_file(&self.model_path)?),
+        }
     }
 
-    None
+    fn return_session(&self, session: Session) {
+        if let Ok(mut idle) = self.sessions.lock() {
+            idle.push(session);
+        }
+    }
 }
 
-pub fn load_labels() -> anyhow::Result<Vec<Label>> {
-    let path = find_model_file("imagenet-simple-labels.json")
-        .ok_or_else(|| anyhow::anyhow!("Labels file not found"))?;
+impl Tagger for ClassifierTagger {
+    fn name(&self) -> &str {
+        &self.manifest.name
+    }
 
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let names: Vec<String> = serde_json::from_reader(reader)?;
-    
-    Ok(names.into_iter().map(|name| Label {
-        name,
-    }).collect())
+    fn version(&self) -> &str {
+        &self.manifest.version
+    }
+
+    fn source(&self) -> TagSource {
+        TagSource::Classifier
+    }
+
+    fn default_crop(&self) -> Option<CropMode> {
+        Some(self.manifest.crop)
+    }
+
+    fn tag(&self, image: &DecodedImage, config: &TaggingConfig) -> anyhow::Result<Vec<ScoredTag>> {
+        Ok(self.analyze(image, config)?.tags)
+    }
+
+    fn analyze(&self, image: &DecodedImage, config: &TaggingConfig) -> anyhow::Result<TaggerOutput> {
+        let mut outputs = self.classify(&[image], config)?;
+        Ok(outputs.pop().unwrap_or_default())
+    }
+
+    fn tag_batch(&self, images: &[&DecodedImage], config: &TaggingConfig) -> Vec<anyhow::Result<TaggerOutput>> {
+        if self.batched && images.len() > 1 && let Ok(outputs) = self.classify(images, config) {
+            return outputs.into_iter().map(Ok).collect();
+        }
+        // A failed batch is retried one by one, so the error lands on the right image
+        images.iter().map(|image| self.analyze(image, config)).collect()
+    }
 }
 
-pub fn generate_tags(
+impl ClassifierTagger {
+    fn classify(&self, images: &[&DecodedImage], config: &TaggingConfig) -> anyhow::Result<Vec<TaggerOutput>> {
+        let mut session = self.take_session()?;
+        let crop = config.crop_modes.get(self.name()).copied().unwrap_or(self.manifest.crop);
+        let outputs = generate_tags(&mut session, &self.manifest, &self.labels, images, config, crop, self.batched);
+        self.return_session(session);
+        outputs
+    }
+}
+
+/// True if the model's first input takes any number of images.
+fn has_dynamic_batch(session: &Session) -> bool {
+    session.inputs().first()
+        .and_then(|input| input.dtype().tensor_shape())
+        .is_some_and(|shape| shape.first() == Some(&-1))
+}
+
+/// Classifies `images`, averaging over the crops of each. Models with a batch
+/// dimension see every crop of every image in one run; fixed-shape models get
+/// one crop at a time. Features from `embedding_output` are averaged the same
+/// way and become the embedding.
+fn generate_tags(
     session: &mut Session,
+    manifest: &ModelManifest,
     labels: &[Label],
-    image_path: &Path,
-) -> anyhow::Result<Vec<String>> {
+    images: &[&DecodedImage],
+    config: &TaggingConfig,
+    crop: CropMode,
+    batched: bool,
+) -> anyhow::Result<Vec<TaggerOutput>> {
+    if images.is_empty() {
+        return Ok(Vec::new());
+    }
+
     // 1. Preprocess
-    let img = ImageReader::open(image_path)?.decode()?;
-    // ResNet expects 224x224
-    let resized = img.resize_exact(224, 224, image::imageops::FilterType::Lanczos3);
-    
-    // Convert to NCHW [1, 3, 224, 224] and Normalize (Mean=[0.485, 0.456, 0.406], Std=[0.229, 0.224, 0.225])
-    let mean = [0.485, 0.456, 0.406];
-    let std = [0.229, 0.224, 0.225];
+    let mut planes = Vec::with_capacity(images.len() * crop.count());
+    let (input_h, input_w) = manifest.input_size();
+    let side = manifest.resize.max(input_h).max(input_w);
+    for image in images {
+        let image_crops = crops(image.at_least(side), crop, manifest.resize, (input_h, input_w));
+        planes.extend(image_crops.iter().map(|c| normalize(c, manifest)));
+    }
     
-    let mut input = Vec::with_capacity(1 * 3 * 224 * 224);
-    // Needed plan: R channel, G channel, B channel separately
-    let rgb = resized.to_rgb8();
+    // 2. Inference
+    let results = if batched {
+        run_model(session, manifest, &planes)?
+    } else {
+        let mut results = Vec::with_capacity(planes.len());
+        for plane in &planes {
+            results.extend(run_model(session, manifest, std::slice::from_ref(plane))?);
+        }
+        results
+    };
     
-    // R channel
-    for pixel in rgb.pixels() {
-        let val = (pixel[0] as f32 / 255.0 - mean[0]) / std[0]; // R
-        input.push(val);
-    }
-    // G channel
-    for pixel in rgb.pixels() {
-         let val = (pixel[1] as f32 / 255.0 - mean[1]) / std[1]; // G
-         input.push(val);
-    }
-    // B channel
-    for pixel in rgb.pixels() {
-        let val = (pixel[2] as f32 / 255.0 - mean[2]) / std[2]; // B
-        input.push(val);
+    // 3. Post-process, averaging the crops of each image
+    Ok(results.chunks(crop.count()).map(|per_crop| {
+        let probs = mean(per_crop.iter().map(|result| &result.probs[..]));
+        let tags = if manifest.multi_label() {
+            select_multi_label(&probs, labels, config, manifest)
+        } else {
+            select_tags(&probs, labels, config, &manifest.name)
+        };
+        let features: Option<Vec<&[f32]>> = per_crop.iter().map(|result| result.features.as_deref()).collect();
+        let embedding = features.map(|features| normalized(&mean(features.into_iter())));
+        TaggerOutput { tags, embedding, ..Default::default() }
+    }).collect())
+}
+
+/// What the model produced for one crop.
+struct CropResult {
+    probs: Vec<f32>,
+    /// Values of `embedding_output`, if the manifest names one.
+    features: Option<Vec<f32>>,
+}
+
+/// Element-wise mean of equally long rows.
+fn mean<'a>(rows: impl ExactSizeIterator<Item = &'a [f32]>) -> Vec<f32> {
+    let count = rows.len() as f32;
+    let mut mean = Vec::new();
+    for row in rows {
+        mean.resize(row.len(), 0.0);
+        for (m, x) in mean.iter_mut().zip(row) {
+            *m += x / count;
+        }
     }
+    mean
+}
 
-    let input_tensor = Value::from_array((vec![1, 3, 224, 224], input.into_boxed_slice()))?;
-    
-    // 2. Inference
+/// Scales `vector` to unit length, so a dot product is cosine similarity.
+pub fn normalized(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt().max(f32::EPSILON);
+    vector.iter().map(|x| x / norm).collect()
+}
+
+/// Runs one batch of preprocessed crops and returns class probabilities, and
+/// features if the manifest asks for them, for each.
+fn run_model(session: &mut Session, manifest: &ModelManifest, planes: &[Vec<f32>]) -> anyhow::Result<Vec<CropResult>> {
+    let (h, w) = manifest.input_size();
+    let (h, w) = (h as usize, w as usize);
+    let shape = match manifest.layout {
+        Layout::Nchw => vec![planes.len(), 3, h, w],
+        Layout::Nhwc => vec![planes.len(), h, w, 3],
+    };
+    let input: Vec<f32> = planes.concat();
+    let input_tensor = Value::from_array((shape, input.into_boxed_slice()))?;
     let outputs = session.run(inputs![input_tensor])?;
     let (_shape, data) = outputs[0].try_extract_tensor::<f32>()?;
-    let logits: Vec<f32> = data.to_vec();
+    let classes = (data.len() / planes.len()).max(1);
+    let mut features = match &manifest.embedding_output {
+        Some(name) => {
+            let output = outputs.get(name).ok_or_else(|| anyhow::anyhow!("Model has no output named {}", name))?;
+            let (_shape, data) = output.try_extract_tensor::<f32>()?;
+            let dim = (data.len() / planes.len()).max(1);
+            data.chunks(dim).map(|row| Some(row.to_vec())).collect()
+        }
+        None => Vec::new(),
+    };
+    features.resize(planes.len(), None);
+    Ok(data.chunks(classes).zip(features).map(|(row, features)| CropResult {
+        probs: match manifest.activation {
+            Activation::Softmax => softmax(row),
+            Activation::Sigmoid => row.iter().map(|&x| 1.0 / (1.0 + (-x).exp())).collect(),
+            Activation::Probabilities => row.to_vec(),
+        },
+        features,
+    }).collect())
+}
+
+/// Scales the shorter side to `resize` without distorting the aspect ratio,
+/// then cuts the crops `mode` asks for at the model's input size.
+fn crops(img: &DynamicImage, mode: CropMode, resize: u32, (crop_h, crop_w): (u32, u32)) -> Vec<DynamicImage> {
+    let (w, h) = (img.width(), img.height());
+    let scale = resize as f32 / w.min(h).max(1) as f32;
+    let w = ((w as f32 * scale).round() as u32).max(crop_w);
+    let h = ((h as f32 * scale).round() as u32).max(crop_h);
+    let resized = img.resize_exact(w, h, image::imageops::FilterType::Lanczos3);
+
+    let (right, bottom) = (w - crop_w, h - crop_h);
+    let center = (right / 2, bottom / 2);
+    let origins = match mode {
+        CropMode::Center => vec![center],
+        CropMode::FiveCrop | CropMode::TenCrop => vec![(0, 0), (right, 0), (0, bottom), (right, bottom), center],
+    };
+    let mut crops: Vec<DynamicImage> = origins.into_iter()
+        .map(|(x, y)| resized.crop_imm(x, y, crop_w, crop_h))
+        .collect();
+    if mode == CropMode::TenCrop {
+        let mirrored: Vec<DynamicImage> = crops.iter().map(DynamicImage::fliph).collect();
+        crops.extend(mirrored);
+    }
+    crops
+}
+
+/// Pixel values for one crop in the model's layout and channel order,
+/// normalized with its mean and std.
+fn normalize(img: &DynamicImage, manifest: &ModelManifest) -> Vec<f32> {
+    let rgb = img.to_rgb8();
+    let order = match manifest.channel_order {
+        ChannelOrder::Rgb => [0, 1, 2],
+        ChannelOrder::Bgr => [2, 1, 0],
+    };
+    let value = |pixel: &image::Rgb<u8>, c: usize| {
+        (pixel[order[c]] as f32 / 255.0 - manifest.mean[c]) / manifest.std[c]
+    };
     
-    // 3. Post-process (Softmax & Top-K)
-    // Softmax
+    let mut input = Vec::with_capacity(rgb.len());
+    match manifest.layout {
+        // One plane per channel
+        Layout::Nchw => {
+            for c in 0..3 {
+                input.extend(rgb.pixels().map(|pixel| value(pixel, c)));
+            }
+        }
+        // Channels interleaved per pixel
+        Layout::Nhwc => {
+            for pixel in rgb.pixels() {
+                input.extend((0..3).map(|c| value(pixel, c)));
+            }
+        }
+    }
+    input
+}
+
+fn softmax(logits: &[f32]) -> Vec<f32> {
     let max_logit = logits.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
     let exp_logits: Vec<f32> = logits.iter().map(|&x| (x - max_logit).exp()).collect();
     let sum_exp: f32 = exp_logits.iter().sum();
-    let probs: Vec<f32> = exp_logits.iter().map(|&x| x / sum_exp).collect();
-    
-    // Sort and get Top 5
+    exp_logits.iter().map(|&x| x / sum_exp).collect()
+}
+
+fn select_tags(probs: &[f32], labels: &[Label], config: &TaggingConfig, model: &str) -> Vec<ScoredTag> {
+    // Sort and keep the most likely labels
     let mut scored_labels: Vec<(usize, f32)> = probs.iter().enumerate().map(|(i, &p)| (i, p)).collect();
     scored_labels.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
     
-    let top_k = 5;
-    let tags: Vec<String> = scored_labels.iter().take(top_k).map(|&(idx, _prob)| {
-        if idx < labels.len() {
-            labels[idx].name.clone()
-        } else {
-            format!("class_{}", idx)
-        }
-    }).collect();
+    let mut cumulative = 0.0;
+    let kept = scored_labels.iter()
+        .take(config.max_tags)
+        .take_while(|&&(_, prob)| prob >= config.min_probability)
+        .take_while(|&&(_, prob)| {
+            // Always keep the label that crosses the cutoff
+            let keep = cumulative < config.cumulative_cutoff;
+            cumulative += prob;
+            keep
+        });
+    kept.map(|&(idx, prob)| ScoredTag::new(label_name(labels, idx), prob, TagSource::Classifier, Some(model)))
+        .collect()
+}
 
-    Ok(tags)
+/// Every class over its own threshold becomes a tag, independently of the
+/// others; the cumulative cutoff does not apply to multi-label models.
+fn select_multi_label(probs: &[f32], labels: &[Label], config: &TaggingConfig, manifest: &ModelManifest) -> Vec<ScoredTag> {
+    let mut kept: Vec<(usize, f32)> = probs.iter().enumerate()
+        .filter(|&(idx, &prob)| {
+            let threshold = labels.get(idx).and_then(|l| l.threshold).unwrap_or(manifest.threshold);
+            prob >= threshold.max(config.min_probability)
+        })
+        .map(|(idx, &prob)| (idx, prob))
+        .collect();
+    kept.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    kept.into_iter()
+        .take(config.max_tags)
+        .map(|(idx, prob)| ScoredTag::new(label_name(labels, idx), prob, TagSource::Classifier, Some(&manifest.name)))
+        .collect()
+}
+
+fn label_name(labels: &[Label], idx: usize) -> String {
+    if idx < labels.len() {
+        labels[idx].name.clone()
+    } else {
+        format!("class_{}", idx)
+    }
 }
@@ -0,0 +1,153 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb, RgbImage};
+use ort::session::Session;
+use ort::value::Tensor;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::detection::{self, BoundingBox, Detection};
+use crate::ml::{self, Label, TaggingConfig};
+use crate::tagger::{DecodedImage, Tagger, TaggerOutput};
+use crate::tags::{ScoredTag, TagSource};
+
+/// Identifier recorded with every tag and box the detector produces.
+pub const DETECTOR_MODEL: &str = "yolo";
+
+/// A YOLOv5/v8-style ONNX export and its class names, one per line or as a
+/// JSON array.
+pub const DETECTOR_FILES: [&str; 2] = ["yolo-detector.onnx", "yolo-labels.txt"];
+
+/// Input side for models exported with a dynamic size.
+const DEFAULT_SIDE: u32 = 640;
+const MIN_SCORE: f32 = 0.35;
+const MAX_OVERLAP: f32 = 0.45;
+/// Grey the letterbox is padded with, as in training.
+const PAD: u8 = 114;
+
+/// Finds objects, stores their boxes and tags each class once with its best
+/// score.
+pub struct ObjectDetector {
+    session: Mutex<Session>,
+    labels: Vec<Label>,
+    /// Input height and width.
+    side: (u32, u32),
+}
+
+impl ObjectDetector {
+    pub fn load() -> anyhow::Result<Self> {
+        let [model, labels] = DETECTOR_FILES.map(|file| {
+            ml::find_model_file(file).ok_or_else(|| anyhow::anyhow!("{} not found", file))
+        });
+        let session = Session::builder()?.commit_from_file(model?)?;
+        let labels = ml::load_labels(&labels?)?;
+        let side = match session.inputs().first().and_then(|i| i.dtype().tensor_shape()).map(|s| s.to_vec()) {
+            Some(shape) if shape.len() == 4 && shape[2] > 0 && shape[3] > 0 => (shape[2] as u32, shape[3] as u32),
+            _ => (DEFAULT_SIDE, DEFAULT_SIDE),
+        };
+        Ok(Self { session: Mutex::new(session), labels, side })
+    }
+
+    pub fn detect(&self, image: &DynamicImage) -> anyhow::Result<Vec<Detection>> {
+        let (h, w) = self.side;
+        let (input, scale, (pad_x, pad_y)) = letterbox(image, w, h);
+        let mut planes = Vec::with_capacity(input.len());
+        for c in 0..3 {
+            planes.extend(input.pixels().map(|pixel| pixel[c] as f32 / 255.0));
+        }
+        let tensor = Tensor::from_array((vec![1, 3, h as usize, w as usize], planes.into_boxed_slice()))?;
+
+        let mut session = self.session.lock().map_err(|_| anyhow::anyhow!("Failed to lock session"))?;
+        let outputs = session.run(ort::inputs![tensor])?;
+        let (shape, data) = outputs[0].try_extract_tensor::<f32>()?;
+        let classes = self.labels.len();
+        let (rows, stride, transposed, objectness) = match shape[..] {
+            // v8 and later: [1, 4 + classes, anchors]
+            [_, c, n] if c as usize == 4 + classes => (n as usize, c as usize, true, false),
+            [_, n, c] if c as usize == 4 + classes => (n as usize, c as usize, false, false),
+            // v5: [1, anchors, 5 + classes] with an objectness score
+            [_, n, c] if c as usize == 5 + classes => (n as usize, c as usize, false, true),
+            _ => anyhow::bail!("Unexpected output shape {:?} for {} labels", &shape[..], classes),
+        };
+        let value = |row: usize, k: usize| if transposed { data[k * rows + row] } else { data[row * stride + k] };
+
+        let (image_w, image_h) = (image.width() as f32, image.height() as f32);
+        let mut candidates: BTreeMap<usize, (Vec<BoundingBox>, Vec<f32>)> = BTreeMap::new();
+        let first_class = if objectness { 5 } else { 4 };
+        for row in 0..rows {
+            let (class, score) = (0..classes)
+                .map(|k| (k, value(row, first_class + k)))
+                .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+                .unwrap_or((0, 0.0));
+            let score = if objectness { score * value(row, 4) } else { score };
+            if score < MIN_SCORE {
+                continue;
+            }
+            // Centre and size in input pixels, back to the original image
+            let (cx, cy, bw, bh) = (value(row, 0), value(row, 1), value(row, 2), value(row, 3));
+            let to_x = |x: f32| (x - pad_x) / scale / image_w;
+            let to_y = |y: f32| (y - pad_y) / scale / image_h;
+            let bbox = BoundingBox::from_corners(to_x(cx - bw / 2.0), to_y(cy - bh / 2.0), to_x(cx + bw / 2.0), to_y(cy + bh / 2.0));
+            let entry = candidates.entry(class).or_default();
+            entry.0.push(bbox);
+            entry.1.push(score);
+        }
+
+        // Suppress per class, so a person on a bike keeps both boxes
+        let mut detections = Vec::new();
+        for (class, (boxes, scores)) in candidates {
+            let label = self.labels.get(class).map(|l| l.name.clone()).unwrap_or_else(|| format!("class {}", class));
+            for i in detection::non_max_suppression(&boxes, &scores, MAX_OVERLAP) {
+                detections.push(Detection { label: label.clone(), score: scores[i], bbox: boxes[i] });
+            }
+        }
+        Ok(detections)
+    }
+}
+
+impl Tagger for ObjectDetector {
+    fn name(&self) -> &str {
+        DETECTOR_MODEL
+    }
+
+    fn version(&self) -> &str {
+        "1"
+    }
+
+    fn source(&self) -> TagSource {
+        TagSource::Detector
+    }
+
+    fn tag(&self, image: &DecodedImage, config: &TaggingConfig) -> anyhow::Result<Vec<ScoredTag>> {
+        Ok(self.analyze(image, config)?.tags)
+    }
+
+    fn analyze(&self, image: &DecodedImage, _config: &TaggingConfig) -> anyhow::Result<TaggerOutput> {
+        let detections = self.detect(&image.image)?;
+        // One tag per label with the best score and the number of instances
+        let mut best: BTreeMap<&str, (f32, u32)> = BTreeMap::new();
+        for detection in &detections {
+            let (score, count) = best.entry(&detection.label).or_insert((0.0, 0));
+            *score = score.max(detection.score);
+            *count += 1;
+        }
+        let tags = best.into_iter()
+            .map(|(label, (score, count))| {
+                ScoredTag::new(label, score, TagSource::Detector, Some(DETECTOR_MODEL)).with_count(count)
+            })
+            .collect();
+        Ok(TaggerOutput { tags, detections: Some(detections), ..Default::default() })
+    }
+}
+
+/// Scales the image to fit `w` x `h` without distortion and pads the rest.
+/// Returns the input, the scale, and the padding on the left and top.
+fn letterbox(image: &DynamicImage, w: u32, h: u32) -> (RgbImage, f32, (f32, f32)) {
+    let scale = (w as f32 / image.width().max(1) as f32).min(h as f32 / image.height().max(1) as f32);
+    let nw = ((image.width() as f32 * scale).round() as u32).clamp(1, w);
+    let nh = ((image.height() as f32 * scale).round() as u32).clamp(1, h);
+    let resized = image.resize_exact(nw, nh, FilterType::Triangle).to_rgb8();
+    let (x, y) = ((w - nw) / 2, (h - nh) / 2);
+    let mut canvas = RgbImage::from_pixel(w, h, Rgb([PAD; 3]));
+    image::imageops::replace(&mut canvas, &resized, x as i64, y as i64);
+    (canvas, scale, (x as f32, y as f32))
+}
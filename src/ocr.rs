@@ -1,66 +1,90 @@
 use oar_ocr::oarocr::{OAROCRBuilder, OAROCR};
-use oar_ocr::utils::load_image;
-use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use crate::ml::TaggingConfig;
+use crate::tagger::{DecodedImage, Tagger, TaggerOutput};
+use crate::tags::{ScoredTag, TagSource};
+
+/// Identifier recorded with every tag the OCR engine produces.
+pub const OCR_MODEL: &str = "oar-ocr";
+
+/// Files the OCR engine needs from the models directory.
+pub const OCR_FILES: [&str; 3] = ["det_model.onnx", "rec_model.onnx", "en_dict.txt"];
+
+/// Recognized text lines plus the individual words as tags.
+pub struct OcrOutput {
+    pub text: String,
+    pub words: Vec<ScoredTag>,
+}
 
 pub struct OcrModel {
-    engine: Option<OAROCR>,
+    engine: OAROCR,
 }
 
 impl OcrModel {
-    pub fn new() -> Self {
-        let det_path: Option<PathBuf> = crate::ml::find_model_file("det_model.onnx");
-        let rec_path: Option<PathBuf> = crate::ml::find_model_file("rec_model.onnx");
-        let keys_path: Option<PathBuf> = crate::ml::find_model_file("en_dict.txt");
-
-        if let (Some(det), Some(rec), Some(keys)) = (det_path, rec_path, keys_path) {
-            // Initialize Builder
-            let build_result = OAROCRBuilder::new(
-                det.to_str().unwrap(),
-                rec.to_str().unwrap(),
-                keys.to_str().unwrap()
-            ).build();
-
-            match build_result {
-                Ok(engine) => Self { engine: Some(engine) },
-                Err(e) => {
-                    eprintln!("Failed to initialize OCR engine: {}", e);
-                    Self { engine: None }
-                }
-            }
-        } else {
-            eprintln!("OCR models/keys not found. Text extraction disabled.");
-            Self { engine: None }
-        }
+    pub fn load() -> anyhow::Result<Self> {
+        let [det, rec, keys] = OCR_FILES.map(|file| {
+            crate::ml::find_model_file(file).ok_or_else(|| anyhow::anyhow!("{} not found", file))
+        });
+        let engine = OAROCRBuilder::new(det?, rec?, keys?)
+            .build()
+            .map_err(|e| anyhow::anyhow!("Failed to initialize OCR engine: {}", e))?;
+        Ok(Self { engine })
     }
 
-    pub fn extract_text(&self, path: &Path) -> anyhow::Result<Vec<String>> {
-        if let Some(engine) = &self.engine {
-            // Load image using oar-ocr utils
-            let img = load_image(path)?;
-            
-            // Run OCR (predict takes a batch, we send 1)
-            let results = engine.predict(vec![img])?;
-            
-            let mut words = Vec::new();
-            for result in results {
-                for region in result.text_regions {
-                     if let Some(text) = region.text {
-                        for word in text.split_whitespace() {
-                            let cleaned: String = word.chars()
-                                .filter(|c| c.is_alphanumeric())
-                                .collect();
-                            if cleaned.len() > 2 {
-                                words.push(cleaned.to_lowercase());
-                            }
+    pub fn extract_text(&self, image: &DecodedImage) -> anyhow::Result<OcrOutput> {
+        let img = image.image.to_rgb8();
+        
+        // Run OCR (predict takes a batch, we send 1)
+        let results = self.engine.predict(vec![img])?;
+        
+        // Keep the best recognition score seen for each word
+        let mut words: HashMap<String, f32> = HashMap::new();
+        let mut lines = Vec::new();
+        for result in results {
+            for region in result.text_regions {
+                 if let Some(text) = region.text {
+                    lines.push(text.to_string());
+                    let confidence = region.confidence.unwrap_or(1.0);
+                    for word in text.split_whitespace() {
+                        let cleaned: String = word.chars()
+                            .filter(|c| c.is_alphanumeric())
+                            .collect();
+                        if cleaned.len() > 2 {
+                            let best = words.entry(cleaned.to_lowercase()).or_insert(0.0);
+                            *best = best.max(confidence);
                         }
-                     }
-                }
+                    }
+                 }
             }
-            words.sort();
-            words.dedup();
-            Ok(words)
-        } else {
-            Ok(Vec::new())
         }
+        let mut tags: Vec<ScoredTag> = words.into_iter()
+            .map(|(word, confidence)| ScoredTag::new(word, confidence, TagSource::Ocr, Some(OCR_MODEL)))
+            .collect();
+        tags.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(OcrOutput { text: lines.join("\n"), words: tags })
+    }
+}
+
+impl Tagger for OcrModel {
+    fn name(&self) -> &str {
+        OCR_MODEL
+    }
+
+    fn version(&self) -> &str {
+        "1"
+    }
+
+    fn source(&self) -> TagSource {
+        TagSource::Ocr
+    }
+
+    fn tag(&self, image: &DecodedImage, config: &TaggingConfig) -> anyhow::Result<Vec<ScoredTag>> {
+        Ok(self.analyze(image, config)?.tags)
+    }
+
+    fn analyze(&self, image: &DecodedImage, _config: &TaggingConfig) -> anyhow::Result<TaggerOutput> {
+        let output = self.extract_text(image)?;
+        Ok(TaggerOutput { tags: output.words, text: Some(output.text), ..Default::default() })
     }
 }
@@ -0,0 +1,33 @@
+use std::env;
+use std::path::PathBuf;
+
+fn xdg_base(var: &str, fallback: &str) -> Option<PathBuf> {
+    if let Some(dir) = env::var_os(var).filter(|v| !v.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback))
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    xdg_base(var, fallback).map(|dir| dir.join("local_lens"))
+}
+
+/// `$XDG_DATA_HOME/local_lens`, usually `~/.local/share/local_lens`.
+pub fn data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// The user's freedesktop trash, `$XDG_DATA_HOME/Trash`.
+pub fn trash_dir() -> Option<PathBuf> {
+    xdg_base("XDG_DATA_HOME", ".local/share").map(|dir| dir.join("Trash"))
+}
+
+/// `$XDG_CONFIG_HOME/local_lens`, usually `~/.config/local_lens`.
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_CACHE_HOME/local_lens`, usually `~/.cache/local_lens`.
+pub fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
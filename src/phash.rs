@@ -0,0 +1,75 @@
+use image::DynamicImage;
+use image::imageops::FilterType;
+use std::f32::consts::PI;
+
+use crate::db::ImageHashes;
+use crate::tagger::DecodedImage;
+
+/// Side of the grayscale image the DCT for pHash runs on.
+const DCT_SIDE: usize = 32;
+/// Low-frequency coefficients kept per axis, giving 64 bits.
+const KEPT: usize = 8;
+
+/// Both hashes, from the preview, plus the full upright size.
+pub fn image_hashes(image: &DecodedImage) -> ImageHashes {
+    ImageHashes {
+        width: image.width,
+        height: image.height,
+        dhash: dhash(&image.preview),
+        phash: phash(&image.preview),
+    }
+}
+
+/// Difference hash: one bit per horizontally adjacent pixel pair of a 9x8
+/// grayscale thumbnail, set when brightness increases. Cheap, and robust to
+/// re-encoding and resizing.
+fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | bit as u64;
+        }
+    }
+    hash
+}
+
+/// Perceptual hash: the 8x8 lowest DCT frequencies of a 32x32 grayscale
+/// thumbnail, one bit per coefficient above their median. Tolerates small
+/// edits, exposure changes and slight shifts better than dHash.
+fn phash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(DCT_SIDE as u32, DCT_SIDE as u32, FilterType::Triangle).to_luma8();
+    let pixels: Vec<f32> = small.pixels().map(|p| p[0] as f32).collect();
+
+    // Separable DCT-II, only for the frequencies that are kept
+    let cos: Vec<f32> = (0..KEPT * DCT_SIDE)
+        .map(|i| {
+            let (u, x) = (i / DCT_SIDE, i % DCT_SIDE);
+            ((2 * x + 1) as f32 * u as f32 * PI / (2 * DCT_SIDE) as f32).cos()
+        })
+        .collect();
+    let mut rows = vec![0.0f32; DCT_SIDE * KEPT];
+    for y in 0..DCT_SIDE {
+        for u in 0..KEPT {
+            rows[y * KEPT + u] = (0..DCT_SIDE).map(|x| pixels[y * DCT_SIDE + x] * cos[u * DCT_SIDE + x]).sum();
+        }
+    }
+    let mut coefficients = Vec::with_capacity(KEPT * KEPT);
+    for v in 0..KEPT {
+        for u in 0..KEPT {
+            coefficients.push((0..DCT_SIDE).map(|y| rows[y * KEPT + u] * cos[v * DCT_SIDE + y]).sum::<f32>());
+        }
+    }
+
+    // The DC term only says how bright the image is
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let median = sorted[sorted.len() / 2];
+    coefficients.iter().fold(0u64, |hash, &c| (hash << 1) | (c > median) as u64)
+}
+
+/// Number of differing bits.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
@@ -1,82 +1,527 @@
-use std::sync::{Arc, Mutex, mpsc, atomic::{AtomicBool, Ordering}};
-use ort::session::Session;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc, atomic::{AtomicBool, AtomicUsize, Ordering}};
+use std::thread;
+use image::metadata::Orientation;
 use crate::app::AppMessage;
-use crate::{db, ml, scanner, ocr};
+use crate::tagger::{DecodedImage, Tagger, TaggerOutput};
+use crate::{db, faces, ml, phash, scanner, thumbnails};
 
 pub fn run_tagging_process(
-    folder: String,
-    session: Arc<Mutex<Session>>,
-    labels: Arc<Vec<ml::Label>>,
-    ocr: Arc<ocr::OcrModel>,
+    roots: Vec<db::LibraryRoot>,
+    taggers: Vec<Arc<dyn Tagger>>,
+    config: ml::TaggingConfig,
     sender: mpsc::Sender<AppMessage>,
     token: Arc<AtomicBool>,
 ) {
-    let images = scanner::scan_images(&folder);
-    let total = images.len();
-    sender.send(AppMessage::Log(format!("Found {} images.", total))).ok();
-    
     // Open separate DB connection for this thread
     let db_conn = match db::setup_db() {
         Ok(conn) => conn,
         Err(e) => {
             sender.send(AppMessage::Log(format!("Failed to open DB: {}", e))).ok();
+            sender.send(AppMessage::Finished).ok();
             return;
         }
     };
-    
-    for (i, img) in images.iter().enumerate() {
-        // Check cancellation
-        if token.load(Ordering::Relaxed) {
+
+    for root in &roots {
+        if !root.path.is_dir() || scanner::is_offline(&root.path) {
+            sender.send(AppMessage::Log(format!("Skipping {} (offline)", root.path.display()))).ok();
+            continue;
+        }
+        sender.send(AppMessage::Log(format!("Scanning {}", root.path.display()))).ok();
+        let folder = root.path.to_string_lossy();
+
+        let run_id = match db::start_run(&db_conn, &folder, &config) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                sender.send(AppMessage::Log(format!("Failed to record tagging run: {}", e))).ok();
+                None
+            }
+        };
+
+        let completed = tag_root(&db_conn, root, &taggers, &config, run_id, &sender, &token);
+        if let Some(run_id) = run_id {
+            db::finish_run(&db_conn, run_id).ok();
+        }
+        if !completed {
+            if let Err(e) = db::assign_root(&db_conn, root) {
+                sender.send(AppMessage::Log(format!("Error saving to DB: {}", e))).ok();
+            }
             sender.send(AppMessage::Log("Tagging cancelled by user.".to_string())).ok();
             sender.send(AppMessage::Finished).ok();
             return;
         }
+        if let Err(e) = db::finish_root_scan(&db_conn, root) {
+            sender.send(AppMessage::Log(format!("Error saving to DB: {}", e))).ok();
+        }
+    }
+    group_faces(&db_conn, &sender);
+    sender.send(AppMessage::Log("Tagging complete.".to_string())).ok();
+    sender.send(AppMessage::Finished).ok();
+}
 
-        sender.send(AppMessage::Progress(i + 1, total)).ok();
-        
-        if scanner::is_already_tagged(img) {
-            sender.send(AppMessage::Log(format!("Skipping {:?} (already tagged)", img.file_name().unwrap_or_default()))).ok();
-            continue;
+/// Sorts new faces into people and tags the images that now show someone
+/// with a name.
+fn group_faces(conn: &rusqlite::Connection, sender: &mpsc::Sender<AppMessage>) {
+    let changed = match faces::cluster_faces(conn) {
+        Ok(changed) => changed,
+        Err(e) => {
+            sender.send(AppMessage::Log(format!("Error grouping faces: {}", e))).ok();
+            return;
         }
+    };
+    for path in changed {
+        update_person_tags(conn, &path, sender);
+    }
+}
 
-        sender.send(AppMessage::Log(format!("Processing {:?}", img.file_name().unwrap_or_default()))).ok();
-        
-        // Scope for mutex lock
+fn update_person_tags(conn: &rusqlite::Connection, path: &Path, sender: &mpsc::Sender<AppMessage>) {
+    if let Err(e) = faces::refresh_person_tags(conn, path) {
+        sender.send(AppMessage::Log(format!("Error saving to DB: {}", e))).ok();
+        return;
+    }
+    let tags = db::get_tag_names(conn, path).unwrap_or_default();
+    match scanner::embed_tags_metadata(path, &tags) {
+        // Otherwise the next scan takes the file for changed and detects its
+        // faces again, dropping them from the person
+        Ok(()) => record_metadata_write(conn, path, sender),
+        Err(e) => {
+            sender.send(AppMessage::Log(format!("Metadata Error {:?}: {}", path.file_name(), e))).ok();
+        }
+    }
+}
+
+/// Names a face cluster and writes the `person:` tag into the database and
+/// the files of every image it appears in.
+pub fn run_person_naming(person_id: i64, name: String, sender: mpsc::Sender<AppMessage>) {
+    let db_conn = match db::setup_db() {
+        Ok(c) => c,
+        Err(e) => {
+            sender.send(AppMessage::Log(format!("DB Error: {}", e))).ok();
+            sender.send(AppMessage::Finished).ok();
+            return;
+        }
+    };
+
+    let images = db::name_person(&db_conn, person_id, &name)
+        .and_then(|person_id| db::get_person_images(&db_conn, person_id));
+    match images {
+        Ok(images) => {
+            let total = images.len();
+            for (i, path) in images.into_iter().enumerate() {
+                sender.send(AppMessage::Progress(i + 1, total)).ok();
+                update_person_tags(&db_conn, &path, &sender);
+            }
+            sender.send(AppMessage::Log(format!("Tagged {} images with {}{}", total, faces::PERSON_PREFIX, name))).ok();
+        }
+        Err(e) => {
+            sender.send(AppMessage::Log(format!("DB Update Error: {}", e))).ok();
+        }
+    }
+    sender.send(AppMessage::Finished).ok();
+}
+
+/// Tags the new and changed images under one root. Returns false if the run
+/// was cancelled or cannot continue.
+fn tag_root(
+    conn: &rusqlite::Connection,
+    root: &db::LibraryRoot,
+    taggers: &[Arc<dyn Tagger>],
+    config: &ml::TaggingConfig,
+    run_id: Option<i64>,
+    sender: &mpsc::Sender<AppMessage>,
+    token: &AtomicBool,
+) -> bool {
+    let images = scanner::scan_images(&root.path, &root.rules());
+    sender.send(AppMessage::Log(format!("Found {} images.", images.len()))).ok();
+
+    let Some((images, summary)) = plan_changes(conn, &root.path, images, token) else {
+        return false;
+    };
+    sender.send(AppMessage::Log(format!(
+        "{} new, {} changed, {} unchanged, {} moved, {} removed.",
+        summary.new, summary.changed, summary.unchanged, summary.moved, summary.removed
+    ))).ok();
+    let total = images.len();
+    let next = AtomicUsize::new(0);
+    let skipped = AtomicUsize::new(0);
+    // Bounded queues keep memory in check: decoders wait while inference is
+    // busy. The decoded queue holds a full batch per worker, since batches
+    // only take what is already queued.
+    let workers = config.inference_workers.max(1);
+    let (decoded_tx, decoded_rx) = mpsc::sync_channel::<(&Path, DecodedImage)>(config.batch_size.max(1) * workers);
+    let decoded_rx = Arc::new(Mutex::new(decoded_rx));
+    let (tagged_tx, tagged_rx) = mpsc::sync_channel::<Tagged>(workers * 2);
+
+    thread::scope(|scope| {
+        let (images, next, skipped) = (&images, &next, &skipped);
+        for _ in 0..config.decode_workers.max(1) {
+            let decoded_tx = decoded_tx.clone();
+            let sender = sender.clone();
+            scope.spawn(move || decode_worker(images, next, skipped, decoded_tx, sender, token));
+        }
+        for _ in 0..config.inference_workers.max(1) {
+            let decoded_rx = decoded_rx.clone();
+            let tagged_tx = tagged_tx.clone();
+            scope.spawn(move || inference_worker(taggers, config, decoded_rx, tagged_tx, token));
+        }
+        // Workers hold the only remaining ends, so the queues close when they finish
+        drop((decoded_tx, decoded_rx, tagged_tx));
+
+        // This thread is the only one writing to the database
+        let mut done = 0;
+        for tagged in &tagged_rx {
+            if token.load(Ordering::Relaxed) {
+                break;
+            }
+            done += 1;
+            sender.send(AppMessage::Progress(done + skipped.load(Ordering::Relaxed), total)).ok();
+            write_tagged(conn, tagged, run_id, sender);
+        }
+        // Unblocks workers still waiting to hand over results
+        drop(tagged_rx);
+    });
+
+    if token.load(Ordering::Relaxed) {
+        return false;
+    }
+    sender.send(AppMessage::Progress(total, total)).ok();
+    true
+}
+
+/// An image with the output of every tagger, on its way to the DB writer.
+struct Tagged<'a> {
+    path: &'a Path,
+    decoded: DecodedImage,
+    results: Vec<(&'a dyn Tagger, anyhow::Result<TaggerOutput>)>,
+}
+
+/// Decodes images in index order until the list runs out or the run is
+/// cancelled. Several of these run at once.
+fn decode_worker<'a>(
+    images: &'a [PathBuf],
+    next: &AtomicUsize,
+    skipped: &AtomicUsize,
+    decoded_tx: mpsc::SyncSender<(&'a Path, DecodedImage)>,
+    sender: mpsc::Sender<AppMessage>,
+    token: &AtomicBool,
+) {
+    while !token.load(Ordering::Relaxed) {
+        let Some(img) = images.get(next.fetch_add(1, Ordering::Relaxed)) else {
+            return;
+        };
+        // Decode once; every tagger and the thumbnail share the pixels
+        let decoded = match DecodedImage::open(img) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                sender.send(AppMessage::Log(format!("Failed to decode {:?}: {}", img.file_name().unwrap_or_default(), e))).ok();
+                skipped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+        let rotated = if decoded.orientation == Orientation::NoTransforms { "" } else { ", rotated" };
+        sender.send(AppMessage::Log(format!(
+            "Processing {:?} ({}x{}{})",
+            img.file_name().unwrap_or_default(), decoded.width, decoded.height, rotated
+        ))).ok();
+        if decoded_tx.send((img, decoded)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Runs every tagger on batches of decoded images. A batch is whatever is
+/// queued, up to the batch size, so a slow decoder never holds inference up.
+/// Taggers that hold a model session take one from their pool per call, so
+/// workers do not wait on each other.
+fn inference_worker<'a>(
+    taggers: &'a [Arc<dyn Tagger>],
+    config: &ml::TaggingConfig,
+    decoded_rx: Arc<Mutex<mpsc::Receiver<(&'a Path, DecodedImage)>>>,
+    tagged_tx: mpsc::SyncSender<Tagged<'a>>,
+    token: &AtomicBool,
+) {
+    while !token.load(Ordering::Relaxed) {
+        let mut batch = Vec::with_capacity(config.batch_size);
         {
-            if let Ok(mut session_guard) = session.lock() {
-                match ml::generate_tags(&mut *session_guard, &labels, img) {
-                    Ok(mut tags) => {
-                            // OCR Extraction
-                            if let Ok(ocr_text) = ocr.extract_text(img) {
-                                for word in ocr_text {
-                                    if !tags.contains(&word) {
-                                        tags.push(word);
-                                    }
-                                }
-                            }
-                            
-                            // 1. Embed metadata
-                            if let Err(e) = scanner::embed_tags_metadata(img, &tags) {
-                            sender.send(AppMessage::Log(format!("Error embedding tags: {}", e))).ok();
-                            }
-                            
-                            // 2. Store in DB
-                            if let Err(e) = db::store_tags(&db_conn, img, &tags) {
-                                sender.send(AppMessage::Log(format!("Error saving to DB: {}", e))).ok();
-                            } 
-                    }
+            let Ok(rx) = decoded_rx.lock() else {
+                return;
+            };
+            match rx.recv() {
+                Ok(first) => batch.push(first),
+                Err(_) => return,
+            }
+            while batch.len() < config.batch_size {
+                match rx.try_recv() {
+                    Ok(more) => batch.push(more),
+                    Err(_) => break,
+                }
+            }
+        }
+
+        let images: Vec<&DecodedImage> = batch.iter().map(|(_, decoded)| decoded).collect();
+        let mut per_tagger: Vec<_> = taggers.iter()
+            .map(|tagger| (tagger.as_ref(), tagger.tag_batch(&images, config).into_iter()))
+            .collect();
+        for (path, decoded) in batch {
+            let results = per_tagger.iter_mut()
+                .map(|(tagger, results)| {
+                    let result = results.next()
+                        .unwrap_or_else(|| Err(anyhow::anyhow!("no result returned")));
+                    (*tagger, result)
+                })
+                .collect();
+            if tagged_tx.send(Tagged { path, decoded, results }).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn write_tagged(
+    conn: &rusqlite::Connection,
+    tagged: Tagged,
+    run_id: Option<i64>,
+    sender: &mpsc::Sender<AppMessage>,
+) {
+    let img = tagged.path;
+
+    // 1. Store in DB, one tagger at a time
+    let mut succeeded = false;
+    for (tagger, result) in tagged.results {
+        match result {
+            Ok(output) => {
+                let stored = db::store_tags(conn, img, tagger.source(), Some(tagger.name()), &output.tags, run_id)
+                    .and_then(|_| match &output.text {
+                        Some(text) => db::store_ocr_text(conn, img, text),
+                        None => Ok(()),
+                    })
+                    .and_then(|_| match &output.embedding {
+                        Some(vector) => db::store_embedding(conn, img, tagger.name(), vector),
+                        None => Ok(()),
+                    })
+                    .and_then(|_| match &output.faces {
+                        Some(faces) => db::store_faces(conn, img, faces),
+                        None => Ok(()),
+                    })
+                    .and_then(|_| match &output.detections {
+                        Some(detections) => db::store_detections(conn, img, tagger.name(), detections),
+                        None => Ok(()),
+                    })
+                    .and_then(|_| match &output.palette {
+                        Some(palette) => db::store_palette(conn, img, palette),
+                        None => Ok(()),
+                    });
+                match stored {
+                    Ok(()) => succeeded = true,
                     Err(e) => {
-                        sender.send(AppMessage::Log(format!("Error generating tags: {}", e))).ok();
+                        sender.send(AppMessage::Log(format!("Error saving to DB: {}", e))).ok();
                     }
                 }
+            }
+            Err(e) => {
+                sender.send(AppMessage::Log(format!("{} failed: {}", tagger.name(), e))).ok();
+            }
+        }
+    }
+    // Leave the file unrecorded so the next scan tries it again
+    if !succeeded {
+        sender.send(AppMessage::Log(format!("No tagger succeeded on {}, will retry next scan", img.display()))).ok();
+        return;
+    }
+
+    if let Err(e) = db::store_image_hashes(conn, img, phash::image_hashes(&tagged.decoded)) {
+        sender.send(AppMessage::Log(format!("Error saving to DB: {}", e))).ok();
+    }
+
+    // 2. Embed metadata, including tags from other sources
+    let names = db::get_tag_names(conn, img).unwrap_or_default();
+    if let Err(e) = scanner::embed_tags_metadata(img, &names) {
+        sender.send(AppMessage::Log(format!("Error embedding tags: {}", e))).ok();
+    }
+
+    // 3. Remember the file as written, so rescans skip it
+    match record_file_state(conn, img) {
+        Ok(hash) => {
+            if let Err(e) = thumbnails::save(&tagged.decoded, &hash) {
+                sender.send(AppMessage::Log(format!("Error writing thumbnail: {}", e))).ok();
+            }
+        }
+        Err(e) => {
+            sender.send(AppMessage::Log(format!("Error saving file state: {}", e))).ok();
+        }
+    }
+}
+
+#[derive(Default)]
+struct ScanSummary {
+    new: usize,
+    changed: usize,
+    unchanged: usize,
+    moved: usize,
+    removed: usize,
+}
+
+/// Records the file's current size, mtime and hash. Returns the hash.
+fn record_file_state(conn: &rusqlite::Connection, path: &Path) -> anyhow::Result<String> {
+    let stat = scanner::file_stat(path)?;
+    let hash = scanner::content_hash(path)?;
+    db::mark_indexed(conn, path, stat, &hash)?;
+    Ok(hash)
+}
+
+/// Records the state of a file whose tags were just rewritten outside a scan,
+/// so the next scan neither re-tags it nor misses it when it moves.
+fn record_metadata_write(conn: &rusqlite::Connection, path: &Path, sender: &mpsc::Sender<AppMessage>) {
+    let old_hash = db::get_file_hash(conn, path).ok().flatten();
+    let hash = match record_file_state(conn, path) {
+        Ok(hash) => hash,
+        Err(e) => {
+            sender.send(AppMessage::Log(format!("Error saving file state: {}", e))).ok();
+            return;
+        }
+    };
+    // Only the metadata changed; decode again if nothing was cached
+    let saved = match old_hash.as_deref().filter(|old| thumbnails::cached(old).is_some()) {
+        Some(old) => thumbnails::copy(old, &hash),
+        None => DecodedImage::open(path).and_then(|decoded| thumbnails::save(&decoded, &hash)),
+    };
+    if let Err(e) = saved {
+        sender.send(AppMessage::Log(format!("Error writing thumbnail: {}", e))).ok();
+    }
+}
+
+/// Compares the scanned files with the index and returns the ones that need
+/// tagging. Size and mtime decide for most files; the content hash is only
+/// read when they differ. A new path whose content matches an indexed file
+/// that no longer exists is treated as a move. Returns `None` if cancelled.
+fn plan_changes(
+    conn: &rusqlite::Connection,
+    root: &Path,
+    images: Vec<PathBuf>,
+    token: &AtomicBool,
+) -> Option<(Vec<PathBuf>, ScanSummary)> {
+    let mut indexed = db::get_indexed_files(conn).unwrap_or_default();
+    let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for (path, file) in &indexed {
+        if let Some(hash) = &file.hash {
+            by_hash.entry(hash.clone()).or_default().push(path.clone());
+        }
+    }
+    let mut summary = ScanSummary::default();
+    let mut pending = Vec::new();
+
+    for img in images {
+        if token.load(Ordering::Relaxed) {
+            return None;
+        }
+        let Some(known) = indexed.remove(&img) else {
+            if let Some(old) = find_moved_from(conn, &img, &mut by_hash) {
+                indexed.remove(&old);
+                summary.moved += 1;
             } else {
-                    sender.send(AppMessage::Log("Failed to lock session".to_string())).ok();
-                    break;
+                summary.new += 1;
+                pending.push(img);
+            }
+            continue;
+        };
+        let Ok(stat) = scanner::file_stat(&img) else {
+            summary.changed += 1;
+            pending.push(img);
+            continue;
+        };
+
+        let unchanged = match (&known.stat, &known.hash) {
+            (Some(old), _) if *old == stat => true,
+            (Some(_), Some(old_hash)) => {
+                // Touched but identical content: refresh the stats only
+                let same = scanner::content_hash(&img).is_ok_and(|h| &h == old_hash);
+                if same {
+                    db::mark_indexed(conn, &img, stat, old_hash).ok();
+                }
+                same
             }
+            // Indexed before stats were kept: trust the marker once
+            (None, _) => scanner::is_already_tagged(&img) && record_file_state(conn, &img).is_ok(),
+            _ => false,
+        };
+        if unchanged {
+            summary.unchanged += 1;
+        } else {
+            summary.changed += 1;
+            pending.push(img);
         }
     }
-    sender.send(AppMessage::Log("Tagging complete.".to_string())).ok();
-    sender.send(AppMessage::Finished).ok();
+
+    summary.removed = indexed.keys().filter(|p| p.starts_with(root)).count();
+    Some((pending, summary))
+}
+
+/// If `img` has the same content as an indexed file that is gone from disk,
+/// moves that row to `img` and returns its old path. Files on an unmounted
+/// drive are not gone, so a copy elsewhere never takes over their row.
+fn find_moved_from(
+    conn: &rusqlite::Connection,
+    img: &Path,
+    by_hash: &mut HashMap<String, Vec<PathBuf>>,
+) -> Option<PathBuf> {
+    let stat = scanner::file_stat(img).ok()?;
+    let hash = scanner::content_hash(img).ok()?;
+    let candidates = by_hash.get_mut(&hash)?;
+    let pos = candidates.iter().position(|p| !p.exists() && !scanner::is_offline(p))?;
+    let old = candidates.remove(pos);
+    db::move_image(conn, &old, img, stat).ok()?;
+    Some(old)
+}
+
+#[derive(Clone, Debug)]
+pub struct StaleEntry {
+    pub path: PathBuf,
+    /// The file's drive is unmounted rather than the file being deleted.
+    pub offline: bool,
+}
+
+/// Finds index entries whose files are gone and reports them for review.
+/// Entries previously marked offline whose drive is back are brought online.
+pub fn run_maintenance_scan(sender: mpsc::Sender<AppMessage>, token: Arc<AtomicBool>) {
+    let db_conn = match db::setup_db() {
+        Ok(c) => c,
+        Err(e) => {
+            sender.send(AppMessage::Log(format!("DB Error: {}", e))).ok();
+            sender.send(AppMessage::StaleEntries(Vec::new())).ok();
+            return;
+        }
+    };
+
+    let paths = db::get_image_paths(&db_conn).unwrap_or_default();
+    let mut stale = Vec::new();
+    let mut back_online = Vec::new();
+    for (path, was_offline) in paths {
+        if token.load(Ordering::Relaxed) {
+            break;
+        }
+        if path.exists() {
+            if was_offline {
+                back_online.push(path);
+            }
+        } else {
+            let offline = scanner::is_offline(&path);
+            // Already flagged and still unmounted, nothing to review
+            if !(offline && was_offline) {
+                stale.push(StaleEntry { path, offline });
+            }
+        }
+    }
+
+    if !back_online.is_empty() {
+        if let Err(e) = db::set_offline(&db_conn, &back_online, false) {
+            sender.send(AppMessage::Log(format!("DB Update Error: {}", e))).ok();
+        } else {
+            sender.send(AppMessage::Log(format!("{} offline images are available again.", back_online.len()))).ok();
+        }
+    }
+    sender.send(AppMessage::StaleEntries(stale)).ok();
 }
 
 pub fn run_renaming_process(
@@ -97,24 +542,22 @@ pub fn run_renaming_process(
         Ok(images) => {
             let total = images.len();
             sender.send(AppMessage::Log(format!("Found {} images with tag '{}'", total, old_tag))).ok();
-            
-            for (i, (path, mut tags)) in images.into_iter().enumerate() {
+
+            // Update DB
+            if let Err(e) = db::rename_tag(&db_conn, &old_tag, &new_tag) {
+                sender.send(AppMessage::Log(format!("DB Update Error: {}", e))).ok();
+                sender.send(AppMessage::Finished).ok();
+                return;
+            }
+
+            for (i, path) in images.into_iter().enumerate() {
                 sender.send(AppMessage::Progress(i + 1, total)).ok();
-                
-                // Precise replacement
-                if let Some(pos) = tags.iter().position(|t| t == &old_tag) {
-                    tags[pos] = new_tag.clone();
-                    // Deduplicate if new tag already existed
-                    tags.sort();
-                    tags.dedup();
-                    
-                    // Update DB
-                    if let Err(e) = db::store_tags(&db_conn, &path, &tags) {
-                            sender.send(AppMessage::Log(format!("DB Update Error: {}", e))).ok();
-                    }
-                    
-                    // Update File
-                    if let Err(e) = scanner::embed_tags_metadata(&path, &tags) {
+
+                // Update File
+                let tags = db::get_tag_names(&db_conn, &path).unwrap_or_default();
+                match scanner::embed_tags_metadata(&path, &tags) {
+                    Ok(()) => record_metadata_write(&db_conn, &path, &sender),
+                    Err(e) => {
                         sender.send(AppMessage::Log(format!("Metadata Error {:?}: {}", path.file_name(), e))).ok();
                     }
                 }
@@ -128,3 +571,48 @@ pub fn run_renaming_process(
     sender.send(AppMessage::Log("Renaming complete.".to_string())).ok();
     sender.send(AppMessage::Finished).ok();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detection::BoundingBox;
+    use crate::faces::Face;
+
+    fn person_of(conn: &rusqlite::Connection) -> Option<i64> {
+        db::get_faces(conn).unwrap().first().and_then(|face| face.person_id)
+    }
+
+    #[test]
+    fn named_person_survives_rescan() {
+        let dir = std::env::temp_dir().join(format!("local_lens_rescan_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let img = dir.join("portrait.png");
+        image::RgbImage::new(8, 8).save(&img).unwrap();
+
+        let conn = db::open_in_memory().unwrap();
+        record_file_state(&conn, &img).unwrap();
+        let face = Face { bbox: BoundingBox { x: 0.1, y: 0.1, w: 0.5, h: 0.5 }, score: 0.9, embedding: vec![1.0, 0.0] };
+        db::store_faces(&conn, &img, &[face]).unwrap();
+        faces::cluster_faces(&conn).unwrap();
+        let person = person_of(&conn).expect("face was clustered");
+        db::name_person(&conn, person, "grandma").unwrap();
+        faces::refresh_person_tags(&conn, &img).unwrap();
+
+        // Writing the person: tag into the file changes its bytes
+        let mut bytes = std::fs::read(&img).unwrap();
+        bytes.extend_from_slice(b"person:grandma");
+        std::fs::write(&img, bytes).unwrap();
+        let never = AtomicBool::new(false);
+        let (pending, _) = plan_changes(&conn, &dir, vec![img.clone()], &never).unwrap();
+        assert_eq!(pending, vec![img.clone()], "an unrecorded write looks like a change");
+
+        record_file_state(&conn, &img).unwrap();
+        let (pending, summary) = plan_changes(&conn, &dir, vec![img.clone()], &never).unwrap();
+        assert!(pending.is_empty());
+        assert_eq!(summary.unchanged, 1);
+        assert_eq!(person_of(&conn), Some(person));
+        assert_eq!(db::get_person_names(&conn, &img).unwrap(), vec!["grandma".to_string()]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,772 @@
+use rusqlite::types::Value;
+use std::fmt;
+
+use crate::colors::{self, Lab};
+use crate::dates::days_from_civil;
+use crate::faces;
+
+// Search query language:
+//
+//   dog AND beach NOT night       boolean operators (AND is implied)
+//   (cat OR dog) -indoor          grouping, `-` as a short NOT
+//   "golden retriever" sun*       phrases and prefix matches
+//   tag:receipt text:"total due"  field prefixes
+//   path:holiday ext:png          substring of the path, file extension
+//   date:2024-05 date:>2023       file modified date, also `a..b` ranges
+//   conf>0.8                      any tag at least this confident
+//   about:"dog on a beach"        images that look like the description (CLIP)
+//   person:grandma                named face cluster, same as tag:person:grandma
+//   count:car>=3                  objects found by the detector, also "label">n
+//   color:blue color:#1e90ff~15   a palette color within ΔE of a name or hex
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    /// Character offset into the query.
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at column {})", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn error<T>(message: impl Into<String>, position: usize) -> Result<T, ParseError> {
+    Err(ParseError { message: message.into(), position })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Text {
+    pub value: String,
+    pub prefix: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cmp {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+}
+
+impl Cmp {
+    fn sql(&self) -> &'static str {
+        match self {
+            Cmp::Gt => ">",
+            Cmp::Ge => ">=",
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Eq => "=",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    /// Bare word or phrase, matched against tags and recognized text.
+    Any(Text),
+    Tag(Text),
+    Text(Text),
+    Path(String),
+    Ext(String),
+    /// Half-open `[start, end)` range in unix seconds.
+    Date(Option<i64>, Option<i64>),
+    Confidence(Cmp, f32),
+    /// Natural-language description, ranked by embedding similarity.
+    About(String),
+    /// Number of detected objects with this label.
+    Count(String, Cmp, u32),
+    /// A palette color within this CIE76 distance of the target.
+    Color(Lab, f32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Term(Term),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Term(Term),
+}
+
+const FIELDS: &[&str] = &["tag", "text", "path", "ext", "date", "about", "person", "count", "color"];
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && c != '(' && c != ')' && c != '"'
+}
+
+struct Lexer<'a> {
+    chars: Vec<(usize, char)>,
+    index: usize,
+    src: &'a str,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Self {
+        Self { chars: src.char_indices().collect(), index: 0, src }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).map(|&(_, c)| c)
+    }
+
+    /// Character (not byte) position of the cursor, for error messages.
+    fn position(&self) -> usize {
+        self.index
+    }
+
+    fn byte_offset(&self) -> usize {
+        self.chars.get(self.index).map(|&(i, _)| i).unwrap_or(self.src.len())
+    }
+
+    fn read_word(&mut self) -> &'a str {
+        let start = self.byte_offset();
+        while self.peek().is_some_and(is_word_char) {
+            self.index += 1;
+        }
+        &self.src[start..self.byte_offset()]
+    }
+
+    fn read_phrase(&mut self) -> Result<String, ParseError> {
+        let open = self.position();
+        self.index += 1;
+        let start = self.byte_offset();
+        while self.peek().is_some_and(|c| c != '"') {
+            self.index += 1;
+        }
+        if self.peek().is_none() {
+            return error("unterminated quote", open);
+        }
+        let phrase = self.src[start..self.byte_offset()].to_string();
+        self.index += 1;
+        Ok(phrase)
+    }
+
+    /// A word or phrase following `field:` or standing on its own.
+    fn read_text(&mut self, position: usize) -> Result<Text, ParseError> {
+        if self.peek() == Some('"') {
+            let value = self.read_phrase()?;
+            let prefix = self.peek() == Some('*');
+            if prefix {
+                self.index += 1;
+            }
+            return Ok(Text { value, prefix });
+        }
+        let word = self.read_word();
+        let (value, prefix) = match word.strip_suffix('*') {
+            Some(stem) => (stem, true),
+            None => (word, false),
+        };
+        if value.is_empty() {
+            return error("expected a word or quoted phrase", position);
+        }
+        Ok(Text { value: value.to_string(), prefix })
+    }
+
+    fn tokens(mut self) -> Result<Vec<(Token, usize)>, ParseError> {
+        let mut tokens = Vec::new();
+        while let Some(c) = self.peek() {
+            let position = self.position();
+            match c {
+                c if c.is_whitespace() => {
+                    self.index += 1;
+                }
+                '(' => {
+                    self.index += 1;
+                    tokens.push((Token::LParen, position));
+                }
+                ')' => {
+                    self.index += 1;
+                    tokens.push((Token::RParen, position));
+                }
+                '-' if self.chars.get(self.index + 1).is_some_and(|&(_, n)| is_word_char(n) || n == '"' || n == '(') => {
+                    self.index += 1;
+                    tokens.push((Token::Not, position));
+                }
+                '"' => {
+                    let text = self.read_text(position)?;
+                    tokens.push((Token::Term(Term::Any(text)), position));
+                }
+                _ => {
+                    let token = self.word_token(position)?;
+                    tokens.push((token, position));
+                }
+            }
+        }
+        Ok(tokens)
+    }
+
+    fn word_token(&mut self, position: usize) -> Result<Token, ParseError> {
+        let start = self.index;
+        let word = self.read_word();
+        match word {
+            "AND" => return Ok(Token::And),
+            "OR" => return Ok(Token::Or),
+            "NOT" => return Ok(Token::Not),
+            _ => {}
+        }
+
+        if let Some(rest) = word.strip_prefix("conf")
+            && rest.starts_with(['<', '>', '='])
+        {
+            let (cmp, number) = split_cmp(rest);
+            return match number.parse::<f32>() {
+                Ok(value) if (0.0..=1.0).contains(&value) => Ok(Token::Term(Term::Confidence(cmp, value))),
+                _ => error("conf expects a number between 0 and 1, e.g. conf>0.8", position),
+            };
+        }
+
+        if let Some((field, _)) = word.split_once(':')
+            && FIELDS.contains(&field)
+        {
+            // Rewind to just after the colon so the value can be a phrase
+            self.index = start + field.chars().count() + 1;
+            let value_position = self.position();
+            if self.peek().is_none_or(|c| c.is_whitespace() || c == ')') {
+                return error(format!("expected a value after '{}:'", field), value_position);
+            }
+            return self.field_token(field, value_position);
+        }
+
+        self.index = start;
+        Ok(Token::Term(Term::Any(self.read_text(position)?)))
+    }
+
+    fn field_token(&mut self, field: &str, position: usize) -> Result<Token, ParseError> {
+        let term = match field {
+            "tag" => Term::Tag(self.read_text(position)?),
+            "text" => Term::Text(self.read_text(position)?),
+            "path" => Term::Path(self.read_text(position)?.value),
+            "about" => Term::About(self.read_text(position)?.value),
+            "person" => {
+                let text = self.read_text(position)?;
+                Term::Tag(Text { value: format!("{}{}", faces::PERSON_PREFIX, text.value), prefix: text.prefix })
+            }
+            "ext" => {
+                let ext = self.read_word().trim_start_matches('.').to_lowercase();
+                if ext.is_empty() {
+                    return error("expected an extension after 'ext:'", position);
+                }
+                Term::Ext(ext)
+            }
+            "date" => {
+                let value = self.read_word();
+                let (start, end) = parse_date_filter(value, position)?;
+                Term::Date(start, end)
+            }
+            "count" => {
+                let (label, comparison) = if self.peek() == Some('"') {
+                    let label = self.read_phrase()?;
+                    (label, self.read_word())
+                } else {
+                    let word = self.read_word();
+                    match word.find(['<', '>', '=']) {
+                        Some(i) => (word[..i].to_string(), &word[i..]),
+                        None => (word.to_string(), ""),
+                    }
+                };
+                let (cmp, number) = split_cmp(comparison);
+                match number.parse::<u32>() {
+                    Ok(n) if !label.is_empty() && !comparison.is_empty() => Term::Count(label, cmp, n),
+                    _ => return error("count expects a label and a number, e.g. count:car>=3", position),
+                }
+            }
+            "color" => {
+                let word = self.read_word();
+                let (color, tolerance) = word.split_once('~').unwrap_or((word, ""));
+                let rgb = colors::named_color(color).or_else(|| colors::parse_hex(color));
+                let tolerance = if tolerance.is_empty() { Some(colors::DEFAULT_TOLERANCE) } else { tolerance.parse::<f32>().ok() };
+                match (rgb, tolerance) {
+                    (Some(rgb), Some(tolerance)) if tolerance >= 0.0 => Term::Color(colors::to_lab(rgb), tolerance),
+                    _ => return error("color expects a name or hex code, e.g. color:blue or color:#1e90ff~15", position),
+                }
+            }
+            _ => unreachable!("field list and match arms out of sync"),
+        };
+        Ok(Token::Term(term))
+    }
+}
+
+fn split_cmp(s: &str) -> (Cmp, &str) {
+    for (op, cmp) in [(">=", Cmp::Ge), ("<=", Cmp::Le), (">", Cmp::Gt), ("<", Cmp::Lt), ("=", Cmp::Eq)] {
+        if let Some(rest) = s.strip_prefix(op) {
+            return (cmp, rest);
+        }
+    }
+    (Cmp::Eq, s)
+}
+
+/// Parses `YYYY`, `YYYY-MM` or `YYYY-MM-DD` into the `[start, end)` range of
+/// unix seconds it covers.
+fn parse_date(s: &str, position: usize) -> Result<(i64, i64), ParseError> {
+    let parts: Vec<&str> = s.split('-').collect();
+    let numbers: Option<Vec<i64>> = parts.iter().map(|p| p.parse().ok()).collect();
+    let bad = || error(format!("invalid date '{}', expected YYYY, YYYY-MM or YYYY-MM-DD", s), position);
+    let Some(numbers) = numbers else {
+        return bad();
+    };
+    let day = 86_400;
+    match numbers.as_slice() {
+        [y] => Ok((days_from_civil(*y, 1, 1) * day, days_from_civil(y + 1, 1, 1) * day)),
+        [y, m] if (1..=12).contains(m) => {
+            let (ny, nm) = if *m == 12 { (y + 1, 1) } else { (*y, m + 1) };
+            Ok((days_from_civil(*y, *m, 1) * day, days_from_civil(ny, nm, 1) * day))
+        }
+        [y, m, d] if (1..=12).contains(m) && (1..=31).contains(d) => {
+            let start = days_from_civil(*y, *m, *d) * day;
+            Ok((start, start + day))
+        }
+        _ => bad(),
+    }
+}
+
+fn parse_date_filter(value: &str, position: usize) -> Result<(Option<i64>, Option<i64>), ParseError> {
+    if let Some((from, to)) = value.split_once("..") {
+        let start = if from.is_empty() { None } else { Some(parse_date(from, position)?.0) };
+        let end = if to.is_empty() { None } else { Some(parse_date(to, position)?.1) };
+        return Ok((start, end));
+    }
+    if value.starts_with(['<', '>', '=']) {
+        let (cmp, date) = split_cmp(value);
+        let (start, end) = parse_date(date, position)?;
+        return Ok(match cmp {
+            Cmp::Gt => (Some(end), None),
+            Cmp::Ge => (Some(start), None),
+            Cmp::Lt => (None, Some(start)),
+            Cmp::Le => (None, Some(end)),
+            Cmp::Eq => (Some(start), Some(end)),
+        });
+    }
+    let (start, end) = parse_date(value, position)?;
+    Ok((Some(start), Some(end)))
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(t, _)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.index).map(|&(_, p)| p).unwrap_or(self.end)
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.index += 1;
+            let right = self.and()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.index += 1,
+                Some(Token::Or | Token::RParen) | None => break,
+                _ => {}
+            }
+            let right = self.unary()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.peek() == Some(&Token::Not) {
+            self.index += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let position = self.position();
+        match self.tokens.get(self.index).map(|(t, _)| t.clone()) {
+            Some(Token::LParen) => {
+                self.index += 1;
+                let expr = self.or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return error("missing closing parenthesis", self.position());
+                }
+                self.index += 1;
+                Ok(expr)
+            }
+            Some(Token::Term(term)) => {
+                self.index += 1;
+                Ok(Expr::Term(term))
+            }
+            Some(Token::RParen) => error("unexpected ')'", position),
+            Some(Token::And | Token::Or | Token::Not) => error("operator is missing a search term", position),
+            None => error("expected a search term", position),
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    let tokens = Lexer::new(input).tokens()?;
+    let mut parser = Parser { tokens, index: 0, end: input.chars().count() };
+    let expr = parser.or()?;
+    if parser.index < parser.tokens.len() {
+        return error("unexpected ')'", parser.position());
+    }
+    Ok(expr)
+}
+
+/// A WHERE clause over `images i` and its positional parameters.
+pub struct Compiled {
+    pub sql: String,
+    pub params: Vec<Value>,
+}
+
+/// Quotes a term for FTS5 so user input can't inject query syntax.
+pub fn fts_term(text: &Text) -> String {
+    let quoted = format!("\"{}\"", text.value.replace('"', "\"\""));
+    if text.prefix { format!("{}*", quoted) } else { quoted }
+}
+
+fn like_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+impl Expr {
+    pub fn compile(&self) -> Compiled {
+        let mut params = Vec::new();
+        let sql = self.compile_into(&mut params);
+        Compiled { sql, params }
+    }
+
+    fn compile_into(&self, params: &mut Vec<Value>) -> String {
+        match self {
+            Expr::And(a, b) => format!("({} AND {})", a.compile_into(params), b.compile_into(params)),
+            Expr::Or(a, b) => format!("({} OR {})", a.compile_into(params), b.compile_into(params)),
+            Expr::Not(a) => format!("(NOT {})", a.compile_into(params)),
+            Expr::Term(term) => term.compile_into(params),
+        }
+    }
+
+    /// Words and phrases that must be present, used for ranking and for
+    /// picking out which tags matched.
+    pub fn positive_terms(&self) -> Vec<Text> {
+        let mut terms = Vec::new();
+        self.collect_positive(&mut terms);
+        terms
+    }
+
+    fn collect_positive(&self, terms: &mut Vec<Text>) {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                a.collect_positive(terms);
+                b.collect_positive(terms);
+            }
+            Expr::Not(_) => {}
+            Expr::Term(Term::Any(text) | Term::Tag(text) | Term::Text(text)) => terms.push(text.clone()),
+            Expr::Term(_) => {}
+        }
+    }
+
+    /// Descriptions from positive `about:` terms, joined into one text to embed.
+    pub fn about_text(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        self.collect_about(&mut parts);
+        if parts.is_empty() { None } else { Some(parts.join(" ")) }
+    }
+
+    fn collect_about(&self, parts: &mut Vec<String>) {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                a.collect_about(parts);
+                b.collect_about(parts);
+            }
+            Expr::Not(_) => {}
+            Expr::Term(Term::About(text)) => parts.push(text.clone()),
+            Expr::Term(_) => {}
+        }
+    }
+
+    /// Treats bare words and phrases as `about:` descriptions, leaving
+    /// negated terms and field filters alone.
+    pub fn into_semantic(self) -> Expr {
+        match self {
+            Expr::And(a, b) => Expr::And(Box::new(a.into_semantic()), Box::new(b.into_semantic())),
+            Expr::Or(a, b) => Expr::Or(Box::new(a.into_semantic()), Box::new(b.into_semantic())),
+            Expr::Term(Term::Any(text)) => Expr::Term(Term::About(text.value)),
+            other => other,
+        }
+    }
+
+    /// FTS5 expression matching any positive full-text term, for BM25 ranking.
+    pub fn rank_match(&self) -> Option<String> {
+        let mut terms = Vec::new();
+        self.collect_ranked(&mut terms);
+        if terms.is_empty() { None } else { Some(terms.join(" OR ")) }
+    }
+
+    fn collect_ranked(&self, terms: &mut Vec<String>) {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                a.collect_ranked(terms);
+                b.collect_ranked(terms);
+            }
+            Expr::Not(_) => {}
+            Expr::Term(Term::Any(text)) => terms.push(fts_term(text)),
+            Expr::Term(Term::Text(text)) => terms.push(format!("text : {}", fts_term(text))),
+            Expr::Term(_) => {}
+        }
+    }
+}
+
+impl Term {
+    fn compile_into(&self, params: &mut Vec<Value>) -> String {
+        match self {
+            Term::Any(text) => {
+                params.push(Value::Text(fts_term(text)));
+                "i.id IN (SELECT rowid FROM image_fts WHERE image_fts MATCH ?)".to_string()
+            }
+            Term::Text(text) => {
+                params.push(Value::Text(format!("text : {}", fts_term(text))));
+                "i.id IN (SELECT rowid FROM image_fts WHERE image_fts MATCH ?)".to_string()
+            }
+            Term::Tag(text) => {
+                let condition = if text.prefix {
+                    params.push(Value::Text(format!("{}%", like_escape(&text.value))));
+                    "t.name LIKE ? ESCAPE '\\'"
+                } else {
+                    params.push(Value::Text(text.value.clone()));
+                    "t.name = ?"
+                };
+                format!(
+                    "i.id IN (SELECT it.image_id FROM image_tags it JOIN tags t ON t.id = it.tag_id WHERE {})",
+                    condition
+                )
+            }
+            Term::Path(fragment) => {
+                params.push(Value::Text(format!("%{}%", like_escape(fragment))));
+                "i.path LIKE ? ESCAPE '\\'".to_string()
+            }
+            Term::Ext(ext) => {
+                params.push(Value::Text(format!("%.{}", like_escape(ext))));
+                "i.path LIKE ? ESCAPE '\\'".to_string()
+            }
+            Term::Date(start, end) => {
+                let mut conditions = Vec::new();
+                if let Some(start) = start {
+                    params.push(Value::Integer(*start));
+                    conditions.push("COALESCE(i.mtime, i.added_at) >= ?");
+                }
+                if let Some(end) = end {
+                    params.push(Value::Integer(*end));
+                    conditions.push("COALESCE(i.mtime, i.added_at) < ?");
+                }
+                if conditions.is_empty() {
+                    "1".to_string()
+                } else {
+                    format!("({})", conditions.join(" AND "))
+                }
+            }
+            Term::Confidence(cmp, value) => {
+                params.push(Value::Real(*value as f64));
+                format!("i.id IN (SELECT image_id FROM image_tags WHERE confidence {} ?)", cmp.sql())
+            }
+            // Ranking happens after the query; here it only needs an embedding
+            Term::About(_) => "i.id IN (SELECT image_id FROM embeddings)".to_string(),
+            // Counted per image so that `<` also matches images with none
+            Term::Count(label, cmp, n) => {
+                params.push(Value::Text(label.clone()));
+                params.push(Value::Integer(*n as i64));
+                format!("(SELECT COUNT(*) FROM detections d WHERE d.image_id = i.id AND d.label = ?) {} ?", cmp.sql())
+            }
+            Term::Color(lab, tolerance) => {
+                params.push(Value::Real(colors::MIN_MATCH_SHARE as f64));
+                for &c in lab {
+                    params.push(Value::Real(c as f64));
+                    params.push(Value::Real(c as f64));
+                }
+                params.push(Value::Real((tolerance * tolerance) as f64));
+                "i.id IN (SELECT image_id FROM palettes WHERE share >= ? AND \
+                 (lab_l - ?) * (lab_l - ?) + (lab_a - ?) * (lab_a - ?) + (lab_b - ?) * (lab_b - ?) <= ?)"
+                    .to_string()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str, prefix: bool) -> Text {
+        Text { value: value.to_string(), prefix }
+    }
+
+    fn any(word: &str) -> Expr {
+        Expr::Term(Term::Any(text(word, false)))
+    }
+
+    fn and(a: Expr, b: Expr) -> Expr {
+        Expr::And(Box::new(a), Box::new(b))
+    }
+
+    fn or(a: Expr, b: Expr) -> Expr {
+        Expr::Or(Box::new(a), Box::new(b))
+    }
+
+    fn not(a: Expr) -> Expr {
+        Expr::Not(Box::new(a))
+    }
+
+    fn term(query: &str) -> Term {
+        match parse(query) {
+            Ok(Expr::Term(term)) => term,
+            other => panic!("{} parsed to {:?}", query, other),
+        }
+    }
+
+    fn parse_error(query: &str) -> (String, usize) {
+        let e = parse(query).expect_err(query);
+        (e.message, e.position)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(parse("a b OR c").unwrap(), or(and(any("a"), any("b")), any("c")));
+        assert_eq!(parse("a OR b AND c").unwrap(), or(any("a"), and(any("b"), any("c"))));
+        assert_eq!(parse("a OR b OR c").unwrap(), or(or(any("a"), any("b")), any("c")));
+    }
+
+    #[test]
+    fn not_applies_to_the_next_term() {
+        assert_eq!(parse("NOT a b").unwrap(), and(not(any("a")), any("b")));
+        assert_eq!(parse("-a -(b OR c)").unwrap(), and(not(any("a")), not(or(any("b"), any("c")))));
+        // A lone dash is a word, not an operator
+        assert_eq!(parse("a - b").unwrap(), and(and(any("a"), any("-")), any("b")));
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert_eq!(parse("(a OR b) c").unwrap(), and(or(any("a"), any("b")), any("c")));
+        assert_eq!(parse("a (b OR (c d))").unwrap(), and(any("a"), or(any("b"), and(any("c"), any("d")))));
+    }
+
+    #[test]
+    fn phrases_and_prefixes() {
+        assert_eq!(
+            parse("\"total due\" sun*").unwrap(),
+            and(Expr::Term(Term::Any(text("total due", false))), Expr::Term(Term::Any(text("sun", true))))
+        );
+        assert_eq!(term("\"golden ret\"*"), Term::Any(text("golden ret", true)));
+        assert_eq!(term("tag:\"hot dog\""), Term::Tag(text("hot dog", false)));
+    }
+
+    #[test]
+    fn field_prefixes() {
+        assert_eq!(term("tag:receipt"), Term::Tag(text("receipt", false)));
+        assert_eq!(term("tag:rec*"), Term::Tag(text("rec", true)));
+        assert_eq!(term("text:\"total due\""), Term::Text(text("total due", false)));
+        assert_eq!(term("path:holiday"), Term::Path("holiday".to_string()));
+        assert_eq!(term("ext:.PNG"), Term::Ext("png".to_string()));
+        assert_eq!(term("person:grandma"), Term::Tag(text("person:grandma", false)));
+        assert_eq!(term("about:\"dog on a beach\""), Term::About("dog on a beach".to_string()));
+        assert_eq!(term("count:car>=3"), Term::Count("car".to_string(), Cmp::Ge, 3));
+        assert_eq!(term("count:\"traffic light\">2"), Term::Count("traffic light".to_string(), Cmp::Gt, 2));
+        assert_eq!(term("color:#1e90ff~15"), Term::Color(colors::to_lab([0x1e, 0x90, 0xff]), 15.0));
+        assert_eq!(
+            term("color:blue"),
+            Term::Color(colors::to_lab(colors::named_color("blue").unwrap()), colors::DEFAULT_TOLERANCE)
+        );
+        // Unknown fields are plain words
+        assert_eq!(term("foo:bar"), Term::Any(text("foo:bar", false)));
+    }
+
+    #[test]
+    fn confidence_comparisons() {
+        assert_eq!(term("conf>0.8"), Term::Confidence(Cmp::Gt, 0.8));
+        assert_eq!(term("conf>=0.5"), Term::Confidence(Cmp::Ge, 0.5));
+        assert_eq!(term("conf<0.2"), Term::Confidence(Cmp::Lt, 0.2));
+        assert_eq!(term("conf<=1"), Term::Confidence(Cmp::Le, 1.0));
+        assert_eq!(term("conf=0"), Term::Confidence(Cmp::Eq, 0.0));
+        assert_eq!(term("conference"), Term::Any(text("conference", false)));
+    }
+
+    #[test]
+    fn date_ranges() {
+        let day = |y, m, d| days_from_civil(y, m, d) * 86_400;
+        assert_eq!(term("date:2024"), Term::Date(Some(day(2024, 1, 1)), Some(day(2025, 1, 1))));
+        assert_eq!(term("date:2024-05"), Term::Date(Some(day(2024, 5, 1)), Some(day(2024, 6, 1))));
+        assert_eq!(term("date:2024-12"), Term::Date(Some(day(2024, 12, 1)), Some(day(2025, 1, 1))));
+        assert_eq!(term("date:2024-02-29"), Term::Date(Some(day(2024, 2, 29)), Some(day(2024, 3, 1))));
+        assert_eq!(term("date:>2023"), Term::Date(Some(day(2024, 1, 1)), None));
+        assert_eq!(term("date:>=2023"), Term::Date(Some(day(2023, 1, 1)), None));
+        assert_eq!(term("date:<2023"), Term::Date(None, Some(day(2023, 1, 1))));
+        assert_eq!(term("date:<=2023"), Term::Date(None, Some(day(2024, 1, 1))));
+        assert_eq!(term("date:2023..2024"), Term::Date(Some(day(2023, 1, 1)), Some(day(2025, 1, 1))));
+        assert_eq!(term("date:..2024-06"), Term::Date(None, Some(day(2024, 7, 1))));
+        assert_eq!(term("date:2024-06.."), Term::Date(Some(day(2024, 6, 1)), None));
+    }
+
+    #[test]
+    fn malformed_queries() {
+        assert_eq!(parse_error("\"open"), ("unterminated quote".to_string(), 0));
+        assert_eq!(parse_error("a )"), ("unexpected ')'".to_string(), 2));
+        assert_eq!(parse_error("(a"), ("missing closing parenthesis".to_string(), 2));
+        assert_eq!(parse_error("a AND"), ("expected a search term".to_string(), 5));
+        assert_eq!(parse_error("AND a"), ("operator is missing a search term".to_string(), 0));
+        assert_eq!(parse_error(""), ("expected a search term".to_string(), 0));
+        assert_eq!(parse_error("tag:"), ("expected a value after 'tag:'".to_string(), 4));
+        assert_eq!(
+            parse_error("x date:2024-13"),
+            ("invalid date '2024-13', expected YYYY, YYYY-MM or YYYY-MM-DD".to_string(), 7)
+        );
+        assert_eq!(parse_error("conf>2").0, "conf expects a number between 0 and 1, e.g. conf>0.8");
+        assert_eq!(parse_error("count:car").0, "count expects a label and a number, e.g. count:car>=3");
+        assert!(parse_error("color:nope").0.starts_with("color expects"));
+        assert_eq!(parse("a )").unwrap_err().to_string(), "unexpected ')' (at column 3)");
+    }
+
+    #[test]
+    fn compiles_to_sql_with_params() {
+        let compiled = parse("tag:dog -ext:png").unwrap().compile();
+        assert_eq!(
+            compiled.sql,
+            "(i.id IN (SELECT it.image_id FROM image_tags it JOIN tags t ON t.id = it.tag_id WHERE t.name = ?) \
+             AND (NOT i.path LIKE ? ESCAPE '\\'))"
+        );
+        assert_eq!(compiled.params, vec![Value::Text("dog".to_string()), Value::Text("%.png".to_string())]);
+
+        let compiled = parse("tag:re_c*").unwrap().compile();
+        assert_eq!(compiled.params, vec![Value::Text("re\\_c%".to_string())]);
+
+        let compiled = parse("\"say \"\" hi\"").map(|e| e.compile());
+        assert!(compiled.is_ok());
+        assert_eq!(fts_term(&text("say \"hi\"", true)), "\"say \"\"hi\"\"\"*");
+    }
+
+    #[test]
+    fn ranking_and_semantic_terms() {
+        let expr = parse("dog text:receipt -cat path:x").unwrap();
+        assert_eq!(expr.rank_match().unwrap(), "\"dog\" OR text : \"receipt\"");
+        let semantic = parse("dog on beach -night").unwrap().into_semantic();
+        assert_eq!(semantic.about_text().unwrap(), "dog on beach");
+    }
+}
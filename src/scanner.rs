@@ -1,22 +1,136 @@
+use std::fs::File;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::UNIX_EPOCH;
 use walkdir::WalkDir;
+use xxhash_rust::xxh3::Xxh3;
 
-pub fn scan_images(folder: &str) -> Vec<PathBuf> {
-    WalkDir::new(folder)
+/// Cheap change indicators read from the filesystem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileStat {
+    pub size: u64,
+    /// Modification time in seconds since the unix epoch.
+    pub mtime: i64,
+}
+
+pub fn file_stat(path: &Path) -> io::Result<FileStat> {
+    let meta = std::fs::metadata(path)?;
+    let mtime = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    Ok(FileStat { size: meta.len(), mtime })
+}
+
+/// XXH3 of the whole file, hex encoded. Only computed when size or mtime
+/// suggest the file changed.
+pub fn content_hash(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:016x}", hasher.digest()))
+}
+
+/// Per-root filters applied while walking a library folder.
+#[derive(Clone, Debug, Default)]
+pub struct ScanRules {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    recursive: bool,
+}
+
+impl ScanRules {
+    /// `include` and `exclude` are comma-separated glob patterns (`*`, `?`)
+    /// matched against the file name and the path relative to the root.
+    pub fn new(include: &str, exclude: &str, recursive: bool) -> Self {
+        let split = |s: &str| -> Vec<String> {
+            s.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect()
+        };
+        Self { include: split(include), exclude: split(exclude), recursive }
+    }
+
+    fn matches(&self, relative: &Path) -> bool {
+        let rel = relative.to_string_lossy();
+        let name = relative.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let hit = |pattern: &String| glob_match(pattern, &name) || glob_match(pattern, &rel);
+        (self.include.is_empty() || self.include.iter().any(hit)) && !self.exclude.iter().any(hit)
+    }
+}
+
+/// Shell-style wildcard match where `*` also crosses `/`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let t: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            // Let the last star swallow one more character
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+pub fn scan_images(folder: &Path, rules: &ScanRules) -> Vec<PathBuf> {
+    let walker = WalkDir::new(folder);
+    let walker = if rules.recursive { walker } else { walker.max_depth(1) };
+    walker
         .into_iter()
         .filter_map(|e| e.ok())
         .filter(|e| {
             e.file_type().is_file()
                 && matches!(
-                    e.path().extension().and_then(|s| s.to_str()),
+                    e.path().extension().and_then(|s| s.to_str()).map(|s| s.to_lowercase()).as_deref(),
                     Some("jpg" | "jpeg" | "png")
                 )
+                && rules.matches(e.path().strip_prefix(folder).unwrap_or(e.path()))
         })
         .map(|e| e.path().to_owned())
         .collect()
 }
 
+/// Mount point of the removable or network drive `path` lives on, judged by
+/// the usual automount locations.
+fn removable_root(path: &Path) -> Option<PathBuf> {
+    let parts: Vec<_> = path.components().take(5).collect();
+    let depth = match parts.iter().map(|c| c.as_os_str().to_str().unwrap_or("")).collect::<Vec<_>>()[..] {
+        [_, "run", "media", _, _, ..] => 5,
+        [_, "media", _, _, ..] => 4,
+        [_, "mnt", _, ..] | [_, "Volumes", _, ..] => 3,
+        _ => return None,
+    };
+    Some(parts[..depth].iter().collect())
+}
+
+/// True if `path` is missing only because its drive isn't mounted. An
+/// unmounted mount point is either gone or left behind as an empty folder.
+pub fn is_offline(path: &Path) -> bool {
+    match removable_root(path) {
+        Some(root) => std::fs::read_dir(&root).map_or(true, |mut entries| entries.next().is_none()),
+        None => false,
+    }
+}
+
 pub fn embed_tags_metadata(path: &Path, tags: &[String]) -> anyhow::Result<()> {
     // Use exiftool to write tags.
     // -overwrite_original: don't create _original backup files
@@ -54,6 +168,8 @@ pub fn embed_tags_metadata(path: &Path, tags: &[String]) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Legacy marker check for files tagged before the index recorded file
+/// stats. Spawns exiftool, so only use it when the DB has nothing to go on.
 pub fn is_already_tagged(path: &Path) -> bool {
     // Check if UserComment contains "local_lens_processed"
     // false
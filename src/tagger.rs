@@ -0,0 +1,111 @@
+use image::metadata::Orientation;
+use image::{DynamicImage, GenericImageView, ImageDecoder, ImageReader};
+use std::path::Path;
+
+use crate::colors::PaletteColor;
+use crate::detection::Detection;
+use crate::faces::Face;
+use crate::ml::{CropMode, TaggingConfig};
+use crate::tags::{ScoredTag, TagSource};
+
+/// Shorter side of `DecodedImage::preview`, enough for the classifier's
+/// resize and crop and for thumbnails.
+const PREVIEW_SIDE: u32 = 384;
+
+/// An image file decoded once and handed to every tagger and to thumbnail
+/// generation.
+pub struct DecodedImage {
+    /// Full resolution, already rotated upright.
+    pub image: DynamicImage,
+    /// EXIF orientation the file was stored with.
+    pub orientation: Orientation,
+    /// Upright dimensions.
+    pub width: u32,
+    pub height: u32,
+    /// Downscaled copy for models with small inputs. Same as `image` when the
+    /// file is already small.
+    pub preview: DynamicImage,
+}
+
+impl DecodedImage {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut decoder = ImageReader::open(path)?.with_guessed_format()?.into_decoder()?;
+        let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+
+        let (width, height) = image.dimensions();
+        let shorter = width.min(height);
+        let preview = if shorter > PREVIEW_SIDE {
+            let scale = PREVIEW_SIDE as f32 / shorter as f32;
+            let w = ((width as f32 * scale).round() as u32).max(1);
+            let h = ((height as f32 * scale).round() as u32).max(1);
+            image.resize_exact(w, h, image::imageops::FilterType::Triangle)
+        } else {
+            image.clone()
+        };
+
+        Ok(Self { image, orientation, width, height, preview })
+    }
+
+    /// The preview if its shorter side has at least `side` pixels, otherwise
+    /// the full image, so models with large inputs never see upscaled pixels.
+    pub fn at_least(&self, side: u32) -> &DynamicImage {
+        if self.preview.width().min(self.preview.height()) >= side { &self.preview } else { &self.image }
+    }
+}
+
+/// What a tagger found in one image.
+#[derive(Default)]
+pub struct TaggerOutput {
+    pub tags: Vec<ScoredTag>,
+    /// Free text read from the image, for full-text search.
+    pub text: Option<String>,
+    /// Unit-length image embedding, for semantic search.
+    pub embedding: Option<Vec<f32>>,
+    /// Faces found, replacing the ones stored before. `None` for taggers
+    /// that do not look for faces.
+    pub faces: Option<Vec<Face>>,
+    /// Objects found, replacing the tagger's earlier ones. `None` for taggers
+    /// that do not detect objects.
+    pub detections: Option<Vec<Detection>>,
+    /// Dominant colors, largest first.
+    pub palette: Option<Vec<PaletteColor>>,
+}
+
+/// A tagging backend. Each enabled tagger sees every new or changed image and
+/// its tags replace the ones it produced on earlier runs.
+pub trait Tagger: Send + Sync {
+    /// Identifier shown in the UI and recorded as the model of every tag.
+    fn name(&self) -> &str;
+
+    fn version(&self) -> &str;
+
+    /// Source recorded with this tagger's tags.
+    fn source(&self) -> TagSource;
+
+    /// Crop mode used unless the settings pick another. `None` for taggers
+    /// that look at the whole image.
+    fn default_crop(&self) -> Option<CropMode> {
+        None
+    }
+
+    /// False when the model files are missing; unavailable taggers are skipped.
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn tag(&self, image: &DecodedImage, config: &TaggingConfig) -> anyhow::Result<Vec<ScoredTag>>;
+
+    /// Tags plus whatever else the tagger reads from the image. Taggers that
+    /// produce text or embeddings override this.
+    fn analyze(&self, image: &DecodedImage, config: &TaggingConfig) -> anyhow::Result<TaggerOutput> {
+        Ok(TaggerOutput { tags: self.tag(image, config)?, ..Default::default() })
+    }
+
+    /// Tags several images, returning one result per image in order. Taggers
+    /// whose model can run a whole batch at once override this.
+    fn tag_batch(&self, images: &[&DecodedImage], config: &TaggingConfig) -> Vec<anyhow::Result<TaggerOutput>> {
+        images.iter().map(|image| self.analyze(image, config)).collect()
+    }
+}
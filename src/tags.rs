@@ -0,0 +1,77 @@
+/// Where a tag came from. Stored as text in `image_tags.source`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TagSource {
+    Classifier,
+    Ocr,
+    Manual,
+    Imported,
+    /// `person:` tags from named face clusters.
+    Person,
+    /// Classes found by the object detector.
+    Detector,
+    /// Named dominant colors.
+    Color,
+}
+
+impl TagSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TagSource::Classifier => "classifier",
+            TagSource::Ocr => "ocr",
+            TagSource::Manual => "manual",
+            TagSource::Imported => "imported",
+            TagSource::Person => "person",
+            TagSource::Detector => "detector",
+            TagSource::Color => "color",
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "classifier" => TagSource::Classifier,
+            "ocr" => TagSource::Ocr,
+            "manual" => TagSource::Manual,
+            "person" => TagSource::Person,
+            "detector" => TagSource::Detector,
+            "color" => TagSource::Color,
+            _ => TagSource::Imported,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ScoredTag {
+    pub name: String,
+    /// Probability in `0.0..=1.0`; manual and imported tags use 1.0.
+    pub confidence: f32,
+    pub source: TagSource,
+    /// Identifier of the model that produced the tag, if any.
+    pub model: Option<String>,
+    /// How many instances the detector found, for object tags.
+    pub count: Option<u32>,
+}
+
+impl ScoredTag {
+    pub fn new(name: impl Into<String>, confidence: f32, source: TagSource, model: Option<&str>) -> Self {
+        Self {
+            name: name.into(),
+            confidence,
+            source,
+            model: model.map(str::to_string),
+            count: None,
+        }
+    }
+
+    pub fn with_count(mut self, count: u32) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    /// `car × 3` for counted tags, the plain name otherwise.
+    pub fn label(&self) -> String {
+        match self.count {
+            Some(n) if n > 1 => format!("{} × {}", self.name, n),
+            _ => self.name.clone(),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use image::ImageFormat;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::paths;
+use crate::tagger::DecodedImage;
+
+/// Longest side of a cached thumbnail, a little over the grid cell size.
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// `$XDG_CACHE_HOME/local_lens/thumbnails`
+fn thumbnail_dir() -> Option<PathBuf> {
+    paths::cache_dir().map(|dir| dir.join("thumbnails"))
+}
+
+/// Cached thumbnail for a file with this content hash, if one was written.
+pub fn cached(hash: &str) -> Option<PathBuf> {
+    thumbnail_dir()
+        .map(|dir| dir.join(format!("{}.jpg", hash)))
+        .filter(|path| path.exists())
+}
+
+/// Files whose metadata was rewritten keep their pixels, so their thumbnail
+/// carries over to the new content hash.
+pub fn copy(old_hash: &str, new_hash: &str) -> anyhow::Result<()> {
+    let old = cached(old_hash).ok_or_else(|| anyhow::anyhow!("No thumbnail for {}", old_hash))?;
+    let new = old.with_file_name(format!("{}.jpg", new_hash));
+    if !new.exists() {
+        fs::copy(old, new)?;
+    }
+    Ok(())
+}
+
+/// Writes the thumbnail for `image` under its content hash. Files with the
+/// same content share one thumbnail.
+pub fn save(image: &DecodedImage, hash: &str) -> anyhow::Result<()> {
+    let dir = thumbnail_dir().ok_or_else(|| anyhow::anyhow!("No cache directory"))?;
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.jpg", hash));
+    if path.exists() {
+        return Ok(());
+    }
+    let thumbnail = image.preview.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+    thumbnail.save_with_format(path, ImageFormat::Jpeg)?;
+    Ok(())
+}